@@ -5,6 +5,7 @@
 import { htmlEsc as esc, downloadBlob } from '@artic-network/pearcore/utils.js';
 import { isNumericType } from './phylograph.js';
 import { graphToNewick } from '@artic-network/pearcore/tree-io.js';
+import { graphToNodeTable } from './tree-utils.js';
 import { viewportDims, compositeViewPng, buildGraphicSVG } from './graphics-io.js';
 import { createGraphicsExporter } from '@artic-network/pearcore/graphics-export.js';
 
@@ -29,6 +30,7 @@ import { createGraphicsExporter } from '@artic-network/pearcore/graphics-export.
  *   openGraphicsDialog:      Function,
  *   doPrint:                 Function,
  *   setExportSaveHandler:    Function,
+ *   setTreeWriter:           Function,
  *   setGraphicsSaveHandler:  Function,
  *   setPrintTrigger:         Function,
  * }}
@@ -50,6 +52,14 @@ export function createExportController({
   // ── Save-handler slot for tree export ──────────────────────────────────────
   let _exportSaveHandler = null;
 
+  // ── Native tree-writer slot ────────────────────────────────────────────────
+  // When set, Newick/NEXUS export hands the tree over as a node table plus
+  // writer options instead of building the output string here, and NHX is
+  // offered as a further format:
+  //   fn({ tree, options, filename, filterName, extensions })
+  // where options = { format, tipLabel, nodeLabel, annotations, settings, subtree }.
+  let _treeWriter = null;
+
   // ── Wire close button ─────────────────────────────────────────────────────
   $('export-tree-close')?.addEventListener('click', _closeExportDialog);
 
@@ -139,6 +149,10 @@ export function createExportController({
         <div class="exp-radio-group">
           <label class="exp-radio-opt"><input type="radio" name="exp-format" value="nexus" checked>&nbsp;NEXUS <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.nexus)</span></label>
           <label class="exp-radio-opt"><input type="radio" name="exp-format" value="newick">&nbsp;Newick <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.nwk)</span></label>
+          ${_treeWriter ? `<label class="exp-radio-opt"><input type="radio" name="exp-format" value="nhx">&nbsp;NHX <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.nhx)</span></label>
+          <label class="exp-radio-opt"><input type="radio" name="exp-format" value="phyloxml">&nbsp;phyloXML <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.xml)</span></label>
+          <label class="exp-radio-opt"><input type="radio" name="exp-format" value="nexml">&nbsp;NeXML <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.xml)</span></label>
+          <label class="exp-radio-opt"><input type="radio" name="exp-format" value="auspice">&nbsp;Auspice JSON <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.json)</span></label>` : ''}
           <label class="exp-radio-opt"><input type="radio" name="exp-format" value="csv">&nbsp;CSV metadata <span style="color:var(--bs-secondary-color);font-size:0.78rem">(.csv)</span></label>
         </div>
       </div>
@@ -374,11 +388,12 @@ export function createExportController({
           if (csvIdRow)    csvIdRow.style.display     = 'none';
           if (nodeLabelRow) nodeLabelRow.style.display = '';
         } else {
+          // NEXUS, or NHX (native writer only), which also carries annotations.
           treeGrid.style.display = '';
           csvGrid.style.display  = 'none';
           treeGrid.querySelectorAll('.exp-annot-cb').forEach(cb => { cb.checked = true; });
-          if (settingsRow)  settingsRow.style.display  = '';
-          if (stateRow)     stateRow.style.display     = '';
+          if (settingsRow)  settingsRow.style.display  = fmt === 'nexus' ? '' : 'none';
+          if (stateRow)     stateRow.style.display     = fmt === 'nexus' ? '' : 'none';
           if (tipLabelRow)  tipLabelRow.style.display  = '';
           if (csvIdRow)    csvIdRow.style.display     = 'none';
           if (nodeLabelRow) nodeLabelRow.style.display = '';
@@ -512,8 +527,9 @@ export function createExportController({
       return;
     }
 
-    const newick = (() => {
-      // Optionally inject _pt_ state annotations for collapsed clades and highlights.
+    // Optionally inject _pt_ state annotations for collapsed clades and
+    // highlights while `fn(annotKeys)` serialises the graph.
+    const withStateAnnotations = (fn) => {
       const storeState = format === 'nexus' && $('exp-store-state')?.checked;
       let finalAnnotKeys = annotKeys;
       const _ptInjected = []; // { node, keys[] } to clean up after
@@ -545,26 +561,71 @@ export function createExportController({
           finalAnnotKeys = [...annotKeys, '_pt_collapsed', '_pt_collapsed_colour', '_pt_highlight', '_pt_hidden'];
         }
       }
-      const result = graphToNewick(graph, subtreeId, finalAnnotKeys, nodeLabelKey, tipNameFn);
-      // Always clean up, even on error, to leave the graph annotations unchanged.
-      for (const { node, keys } of _ptInjected) {
-        for (const k of keys) delete node.annotations[k];
+      try {
+        return fn(finalAnnotKeys);
+      } finally {
+        // Always clean up, even on error, to leave the graph annotations unchanged.
+        for (const { node, keys } of _ptInjected) {
+          for (const k of keys) delete node.annotations[k];
+        }
+      }
+    };
+
+    const ext        = { nexus: 'nexus', nhx: 'nhx', phyloxml: 'xml', nexml: 'xml', auspice: 'json' }[format] ?? 'nwk';
+    const filterName = { nexus: 'NEXUS files', nhx: 'NHX files', phyloxml: 'phyloXML files', nexml: 'NeXML files', auspice: 'Auspice JSON files' }[format] ?? 'Newick files';
+
+    // ── Native writer: hand over the node table, Rust writes the file ────────
+    if (_treeWriter) {
+      // A plain annotation chosen as the tip label is resolved by the writer;
+      // display-formatted names are resolved here.
+      const tipLabel = ['__as_displayed__', 'name', 'names'].includes(tipLabelKey) ? null : tipLabelKey;
+      const tableTipName = tipLabel ? (gNode => gNode.name ?? gNode.origId) : tipNameFn;
+      const { tree, rowOf, keys } = withStateAnnotations(keys => ({
+        ...graphToNodeTable(graph, [...keys, nodeLabelKey, tipLabel].filter(Boolean), tableTipName),
+        keys,
+      }));
+      // Auspice wants decimal dates on every node: take them from the active
+      // calibration wherever the tree does not already carry num_date.
+      if (format === 'auspice' && renderer._calCalibration?.isActive) {
+        for (const [id, row] of rowOf) {
+          const rNode = renderer.nodeMap?.get(id);
+          const date  = rNode ? renderer._statValue(rNode, '__cal_date__') : null;
+          if (date != null) tree.nodes[row].annotations.num_date ??= date;
+        }
+        if (!keys.includes('num_date')) keys.push('num_date');
       }
-      return result;
-    })();
+      _treeWriter({
+        tree,
+        options: {
+          format,
+          tipLabel,
+          nodeLabel:   nodeLabelKey,
+          annotations: keys,
+          // Auspice takes its colourings and default colour-by from the settings.
+          settings:    (storeSettings || format === 'auspice') ? getSettingsSnapshot() : null,
+          subtree:     subtreeId ? (rowOf.get(subtreeId) ?? null) : null,
+        },
+        filename:   `tree.${ext}`,
+        filterName,
+        extensions: [ext],
+      });
+      _closeExportDialog();
+      return;
+    }
+
+    const newick = withStateAnnotations(keys =>
+      graphToNewick(graph, subtreeId, keys, nodeLabelKey, tipNameFn));
     if (!newick) return;
 
-    let content, ext;
+    let content;
     if (format === 'nexus') {
       const rootedTag    = annotKeys.length > 0 ? '[&R] ' : '';
       const settingsLine = storeSettings
         ? `\t[peartree=${JSON.stringify(getSettingsSnapshot())}]\n`
         : '';
       content = `#NEXUS\nBEGIN TREES;\n\ttree TREE1 = ${rootedTag}${newick}\n${settingsLine}END;\n`;
-      ext     = 'nexus';
     } else {
       content = newick + '\n';
-      ext     = 'nwk';
     }
 
     if (_exportSaveHandler) {
@@ -572,7 +633,7 @@ export function createExportController({
         content,
         filename:   `tree.${ext}`,
         mimeType:   'text/plain',
-        filterName: format === 'nexus' ? 'NEXUS files' : 'Newick files',
+        filterName,
         extensions: [ext],
       });
     } else {
@@ -590,6 +651,7 @@ export function createExportController({
     closeGraphicsDialog:    _gfx.close,
     doPrint:                _gfx.doPrint,
     setExportSaveHandler:   (fn) => { _exportSaveHandler   = fn; },
+    setTreeWriter:          (fn) => { _treeWriter          = fn; },
     setGraphicsSaveHandler: _gfx.setSaveHandler,
     setPrintTrigger:        _gfx.setPrintTrigger,
   };
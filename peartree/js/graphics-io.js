@@ -6,7 +6,8 @@ import { AxisRenderer } from './axis-renderer.js';
 import { isNumericType, TreeCalibration } from './phylograph.js';
 import { getSequentialPalette,
          DEFAULT_CATEGORICAL_PALETTE, DEFAULT_SEQUENTIAL_PALETTE,
-         MISSING_DATA_COLOUR, buildCategoricalColourMap } from '@artic-network/pearcore/palettes.js';
+         MISSING_DATA_COLOUR } from '@artic-network/pearcore/palettes.js';
+import { categoricalColourMap } from './legend-renderer.js';
 import { htmlEsc as esc } from '@artic-network/pearcore/utils.js';
 
 /** @private SVG text-content escaper (no quot needed here). */
@@ -224,7 +225,7 @@ export function buildGraphicSVG(ctx, fullTree = false, transparent = false) {
 
     if (def.dataType === 'categorical' || def.dataType === 'ordinal') {
       const paletteName = lr._paletteOverrides?.get(key);
-      const colourMap   = buildCategoricalColourMap(def.values || [], paletteName);
+      const colourMap   = categoricalColourMap(def.values || [], paletteName);
       const SWATCH = Math.max(8, lfs);
       const ROW_H  = Math.max(SWATCH + 4, lfs + 4);
       (def.values || []).forEach((val) => {
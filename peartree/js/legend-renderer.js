@@ -23,6 +23,18 @@ import { getSequentialPalette,
 import { dateToDecimalYear, isNumericType } from './phylograph.js';
 import { buildFont, TYPEFACES } from '@artic-network/pearcore/typefaces.js';
 
+/**
+ * Colour map for categorical `values` under a palette override, which is
+ * either a palette name or a fixed `{ value: colour }` map (as seeded from
+ * Auspice colourings).  Values the fixed map leaves out take the default
+ * palette.
+ */
+export function categoricalColourMap(values, override) {
+  if (!override || typeof override === 'string') return buildCategoricalColourMap(values, override);
+  const rest = buildCategoricalColourMap(values.filter(v => !(String(v) in override)), null);
+  return new Map(values.map(v => [v, override[String(v)] ?? rest.get(v)]));
+}
+
 export class LegendRenderer {
   /**
    * @param {HTMLCanvasElement} rightCanvas
@@ -40,7 +52,7 @@ export class LegendRenderer {
     this._position   = null;   // 'right' | null
     this._annotation = null;   // annotation key string | null
     this._schema     = null;   // Map<string, AnnotationDef>
-    this._paletteOverrides = null; // Map<annotKey, paletteName> from TreeRenderer
+    this._paletteOverrides = null; // Map<annotKey, paletteName | {value: colour}> from TreeRenderer
 
     this._annotation2  = null;    // second legend annotation key | null
     this._position2    = 'right'; // 'right' (beside L1) | 'below' (stacked under L1)
@@ -632,7 +644,7 @@ export class LegendRenderer {
 
     if (def.dataType === 'categorical' || def.dataType === 'ordinal') {
       const paletteName = this._paletteOverrides?.get(key);
-      const colourMap   = buildCategoricalColourMap(def.values || [], paletteName);
+      const colourMap   = categoricalColourMap(def.values || [], paletteName);
       const SWATCH   = Math.max(8, lfs);
       const ROW_H    = Math.max(SWATCH + 4, lfs + 4);
       const vals     = def.values || [];
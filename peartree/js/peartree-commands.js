@@ -8,6 +8,7 @@ export const COMMAND_DEFS = [
   { id: 'new-window',   label: 'New Window',             shortcut: 'CmdOrCtrl+N',             group: 'file', enabled: true  },
   { id: 'open-file',    label: 'Open…',                 shortcut: 'CmdOrCtrl+O',             group: 'file', enabled: true  },
   { id: 'open-tree',    label: 'Open Tree…',             shortcut: 'CmdOrCtrl+Shift+O',       group: 'file', enabled: true,  buttonId: 'btn-open-tree'      },
+  { id: 'open-usher-subtree', label: 'Open UShER Subtree…', shortcut: null,                  group: 'file', enabled: false },
   { id: 'import-annot', label: 'Import Annotations…',    shortcut: 'CmdOrCtrl+Shift+A',       group: 'file', enabled: false, buttonId: 'btn-import-annot'   },
   { id: 'curate-annot',   label: 'Curate Annotations…',    shortcut: null,                      group: 'file', enabled: false, buttonId: 'btn-curate-annot'   },
   { id: 'manage-filters', label: 'Manage Filters…',        shortcut: null,                      group: 'file', enabled: false, buttonId: 'btn-manage-filters' },
@@ -48,6 +49,7 @@ export const COMMAND_DEFS = [
   { id: 'tree-midpoint',             label: 'Midpoint Root',            shortcut: 'CmdOrCtrl+M',       group: 'tree', enabled: false, buttonId: 'btn-midpoint-root'        },
   { id: 'tree-temporal-root',        label: 'Optimise Root on Branch',  shortcut: 'CmdOrCtrl+Shift+T', group: 'tree', enabled: false, buttonId: 'btn-temporal-root'        },
   { id: 'tree-temporal-root-global', label: 'Global Temporal Root',     shortcut: 'CmdOrCtrl+T',       group: 'tree', enabled: false, buttonId: 'btn-temporal-root-global' },
+  { id: 'tree-time-tree',            label: 'Estimate Time Tree…',      shortcut: null,                group: 'tree', enabled: false },
   { id: 'tree-hide',                 label: 'Hide Nodes',               shortcut: 'CmdOrCtrl+Backspace',       group: 'tree', enabled: false, buttonId: 'btn-hide'                 },
   { id: 'tree-show',                 label: 'Show Nodes',               shortcut: 'CmdOrCtrl+Shift+Backspace', group: 'tree', enabled: false, buttonId: 'btn-show'                 },
   { id: 'tree-collapse-clade',       label: 'Collapse Clade',           shortcut: 'CmdOrCtrl+1',       group: 'tree', enabled: false, buttonId: 'btn-collapse-clade'       },
@@ -56,6 +58,12 @@ export const COMMAND_DEFS = [
   { id: 'tree-clear-colours',        label: 'Clear Colours',            shortcut: 'CmdOrCtrl+Shift+K', group: 'tree', enabled: false, buttonId: 'btn-clear-user-colour'    },
   { id: 'tree-highlight-clade',      label: 'Highlight Clade',          shortcut: 'CmdOrCtrl+Shift+L', group: 'tree', enabled: false, buttonId: 'btn-highlight-clade'      },
   { id: 'tree-clear-highlights',     label: 'Remove Highlight',         shortcut: null,                group: 'tree', enabled: false, buttonId: 'btn-clear-highlights'     },
+  { id: 'tree-prev-tree',            label: 'Previous Tree',            shortcut: 'CmdOrCtrl+Alt+[',   group: 'tree', enabled: false },
+  { id: 'tree-next-tree',            label: 'Next Tree',                shortcut: 'CmdOrCtrl+Alt+]',   group: 'tree', enabled: false },
+  { id: 'tree-goto-tree',            label: 'Go to Tree…',              shortcut: null,                group: 'tree', enabled: false },
+  { id: 'tree-consensus',            label: 'Consensus Tree…',          shortcut: null,                group: 'tree', enabled: false },
+  { id: 'tree-mcc',                  label: 'MCC Tree…',                shortcut: null,                group: 'tree', enabled: false },
+  { id: 'tree-compare',              label: 'Compare Trees…',           shortcut: null,                group: 'tree', enabled: false },
 
   // Help
   { id: 'show-help',         label: 'PearTree Help',      shortcut: 'CmdOrCtrl+?', group: 'help', enabled: true, buttonId: 'btn-help' },
@@ -3,6 +3,22 @@
  *
  * Extends the generic pearcore Tauri adapter with:
  *   • Native tree file picker     (pick_tree_file command)
+ *   • Native tree parsing         (parse_tree command → NEXUS/Newick/phyloXML/NeXML/Auspice/Taxonium/UShER/jplace node table)
+ *   • Load progress + cancel      (load-progress-{label} events, cancel_load)
+ *   • Multi-tree files            (get_tree command; Next/Previous/Go to Tree)
+ *   • Tree-set summaries          (build_consensus / build_mcc → new window,
+ *                                  take_pending_tree)
+ *   • Native tree export          (export_tree command writes Newick/NEXUS)
+ *   • Native rooting              (root_tree command: reroot, midpoint, temporal)
+ *   • Time-tree estimation        (estimate_time_tree → new window)
+ *   • RTT regression report       (export_rtt_report saves CSV, then summary)
+ *   • Date-randomisation test     (date_randomisation_test, cancellable job
+ *                                  with date-test-progress-{label} events,
+ *                                  cancel_date_test)
+ *   • UShER neighbourhoods        (open_usher_subtree → new window)
+ *   • Tree comparison             (tree_windows / compare_trees: RF, branch
+ *                                  score and quartet distances;
+ *                                  forget_window_tree after non-native loads)
  *   • Open-tree command override  (bypasses modal, uses native picker)
  *   • Import annotations override (pick_annot_file + config step)
  *   • Pending file load           (new window opened for a specific file)
@@ -34,6 +50,307 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
     appName:  'PearTree',
   });
 
+  // ── Native tree parsing ────────────────────────────────────────────────
+  // parse_tree returns a pre-order node table: rows of
+  // { parent, length?, label?, annotations? } with the root at row 0.  Rebuild
+  // the nested { id, name, length, annotations, children } shape produced by
+  // parseNewick so loadTree can hand it straight to fromNestedRoot.
+  // Internal-node labels go under the "_node_label" sentinel, as in parseNewick.
+  function nodeTableToNested(rows) {
+    const nodes = rows.map((row, i) => ({ id: `n${i}`, annotations: row.annotations ?? {} }));
+    rows.forEach((row, i) => {
+      const node = nodes[i];
+      if (row.length != null) node.length = row.length;
+      if (row.parent != null) {
+        const parent = nodes[row.parent];
+        (parent.children ??= []).push(node);
+      }
+    });
+    rows.forEach((row, i) => {
+      if (row.label == null) return;
+      const node = nodes[i];
+      if (node.children) node.annotations._node_label = row.label;
+      else node.name = row.label;
+    });
+    return nodes[0];
+  }
+
+  // ── Load progress / cancellation ─────────────────────────────────────────
+  // Native loads run as background jobs that emit load-progress-{label} to
+  // this window only.  Show the percentage in the title bar while a load is
+  // running; Escape cancels it (the command then fails with kind 'cancelled').
+  let _loadInFlight = false;
+  await currentWindow.listen(`load-progress-${currentWindow.label}`, (event) => {
+    const { bytesRead, totalBytes } = event.payload ?? {};
+    if (!_loadInFlight || !totalBytes) return;
+    document.title = `Loading… ${Math.floor(100 * bytesRead / totalBytes)}% — PearTree`;
+  });
+  document.addEventListener('keydown', (e) => {
+    if (_loadInFlight && e.key === 'Escape') invoke('cancel_load').catch(() => {});
+  });
+
+  // Load a tree from disk, parsing it in Rust.  Falls back to reading the raw
+  // text and parsing in JS when the native parser rejects the file.
+  // The result is passed to loadTree in the same shape as a parseNexus() entry.
+  async function loadTreeFromPath(path) {
+    const name = path.split(/[\\/]/).pop() || 'tree';
+    const title = document.title;
+    let parsed = null, content = null;
+    _loadInFlight = true;
+    try {
+      try {
+        parsed = await invoke('parse_tree', { path });
+      } catch (err) {
+        if (err?.kind === 'cancelled') throw err;
+        console.warn('Native parse failed, falling back to JS parser:', err);
+        content = await invoke('read_file_content', { path });
+      }
+    } catch (err) {
+      if (err?.kind !== 'cancelled') throw err;
+      document.title = title;
+      if (!app.hasTree) document.getElementById('empty-state')?.classList.remove('hidden');
+      return;
+    } finally {
+      _loadInFlight = false;
+    }
+    _treeSet = parsed?.index ? { name, current: 0, count: parsed.index.count } : null;
+    _updateTreeNav();
+    if (content !== null) {
+      await app.loadTree(content, name);
+      return;
+    }
+    await showParsedTree(parsed, name);
+  }
+
+  // Annotations that came from Auspice branch labels (`k_label`), so an
+  // Auspice export writes them back as branch labels rather than node data.
+  let _branchLabels = [];
+
+  // Rust keeps this window's tree for Compare Trees only while the tree shown
+  // came from showParsedTree.  Any other load (the JS-parser fallback, paste,
+  // a dropped text file) tells Rust to forget it so no stale tree is compared,
+  // and has no Auspice branch labels.
+  let _nativeLoad = false;
+  app.onTreeLoad(() => {
+    if (_nativeLoad) return;
+    invoke('forget_window_tree').catch(() => {});
+    _branchLabels = [];
+  });
+
+  // Hand one parse_tree / get_tree result to loadTree.
+  async function showParsedTree(parsed, name) {
+    const mismatch = parsed.warnings.find(w => w.kind === 'taxaBlockMismatch');
+    _branchLabels = parsed.settings?.auspiceBranchLabels ?? [];
+    _nativeLoad = true;
+    try {
+      await app.loadTree({
+        root:               nodeTableToNested(parsed.trees[0].nodes),
+        peartreeSettings:   parsed.settings ?? null,
+        taxonAnnotWarnings: mismatch ? mismatch.names : [],
+      }, name);
+    } finally {
+      _nativeLoad = false;
+    }
+  }
+
+  // ── Multi-tree files ─────────────────────────────────────────────────────
+  // When parse_tree reports an index (more than one tree in the file), Rust
+  // keeps the file in memory for this window and get_tree parses any other
+  // tree on demand.  Tree numbers shown to the user are 1-based.
+  let _treeSet = null;
+
+  function _updateTreeNav() {
+    const many = _treeSet !== null;
+    registry.setEnabled('tree-prev-tree', many && _treeSet.current > 0);
+    registry.setEnabled('tree-next-tree', many && _treeSet.current < _treeSet.count - 1);
+    registry.setEnabled('tree-goto-tree', many);
+    registry.setEnabled('tree-consensus', many);
+    registry.setEnabled('tree-mcc',       many);
+  }
+
+  async function showTreeAt(index) {
+    if (!_treeSet || index < 0 || index >= _treeSet.count || index === _treeSet.current) return;
+    try {
+      const parsed = await invoke('get_tree', { index });
+      _treeSet.current = index;
+      _updateTreeNav();
+      await showParsedTree(parsed, `${_treeSet.name} [${index + 1}/${_treeSet.count}]`);
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  }
+
+  registry.get('tree-prev-tree').exec = () => showTreeAt(_treeSet.current - 1);
+  registry.get('tree-next-tree').exec = () => showTreeAt(_treeSet.current + 1);
+  registry.get('tree-goto-tree').exec = async () => {
+    const answer = await app.showPromptDialog(
+      'Go to Tree',
+      `This file contains ${_treeSet.count} trees. Which tree should be shown (1–${_treeSet.count})?`,
+      String(_treeSet.current + 1),
+    );
+    if (answer == null) return;
+    const n = parseInt(answer, 10);
+    if (!Number.isInteger(n) || n < 1 || n > _treeSet.count) {
+      app.showErrorDialog(`Enter a tree number between 1 and ${_treeSet.count}.`);
+      return;
+    }
+    await showTreeAt(n - 1);
+  };
+
+  // ── Tree-set summaries ──────────────────────────────────────────────────
+  // Rust builds the summary tree from the open tree set and opens it in a new
+  // window.  Consensus: ask for the clade threshold and burn-in.
+  registry.get('tree-consensus').exec = async () => {
+    const pct = await app.showPromptDialog(
+      'Consensus Tree',
+      'Keep clades found in at least this percentage of trees (50 for majority-rule):',
+      '50',
+    );
+    if (pct == null) return;
+    const threshold = parseFloat(pct) / 100;
+    if (!(threshold >= 0 && threshold <= 1)) {
+      app.showErrorDialog('Enter a percentage between 0 and 100.');
+      return;
+    }
+    const burnin = await promptBurnin('Consensus Tree', '0');
+    if (burnin == null) return;
+    try {
+      await invoke('build_consensus', { threshold, burnin });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  };
+
+  // MCC tree (TreeAnnotator equivalent): BEAST samples usually need a burn-in,
+  // so default to discarding the first 10%.
+  registry.get('tree-mcc').exec = async () => {
+    const burnin = await promptBurnin('MCC Tree', String(Math.floor(_treeSet.count / 10)));
+    if (burnin == null) return;
+    try {
+      await invoke('build_mcc', { burnin });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  };
+
+  // Ask how many leading trees to discard; null if cancelled or invalid.
+  async function promptBurnin(title, defaultValue) {
+    const skip = await app.showPromptDialog(
+      title,
+      `Number of trees to discard as burn-in (of ${_treeSet.count}):`,
+      defaultValue,
+    );
+    if (skip == null) return null;
+    const burnin = parseInt(skip, 10);
+    if (!Number.isInteger(burnin) || burnin < 0 || burnin >= _treeSet.count) {
+      app.showErrorDialog(`Enter a burn-in between 0 and ${_treeSet.count - 1}.`);
+      return null;
+    }
+    return burnin;
+  }
+
+  // ── Tree comparison ─────────────────────────────────────────────────────
+  // Compare this window's tree with another window's, or with a file; a
+  // window with no tree compares two files.  Rust computes the distances on
+  // the shared taxa.  File reads show progress and cancel like loads.
+  registry.setEnabled('tree-compare', true);
+  registry.get('tree-compare').exec = async () => {
+    let windows;
+    try {
+      windows = await invoke('tree_windows');
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+      return;
+    }
+    const here   = windows.some(w => w.label === currentWindow.label);
+    const others = windows.filter(w => w.label !== currentWindow.label);
+    let second = { kind: 'file', path: null };
+    if (here && others.length > 0) {
+      const list = others.map((w, i) => `${i + 1} = ${w.title}`).join('; ');
+      const answer = await app.showPromptDialog(
+        'Compare Trees',
+        `Compare this tree with which window (${list})? Leave blank to choose a file.`,
+        '1',
+      );
+      if (answer == null) return;
+      if (answer.trim() !== '') {
+        const n = parseInt(answer, 10);
+        if (!Number.isInteger(n) || n < 1 || n > others.length) {
+          app.showErrorDialog(`Enter a window number between 1 and ${others.length}, or leave it blank.`);
+          return;
+        }
+        second = { kind: 'window', label: others[n - 1].label };
+      }
+    }
+    const first = here ? { kind: 'window', label: currentWindow.label } : { kind: 'file', path: null };
+    const title = document.title;
+    let result;
+    _loadInFlight = true;
+    try {
+      result = await invoke('compare_trees', { first, second });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+      return;
+    } finally {
+      _loadInFlight = false;
+      document.title = title;
+    }
+    if (result) await app.showAlertDialog('Compare Trees', comparisonReport(result));
+  };
+
+  // One paragraph for the alert dialog; long lists of unshared taxa are cut.
+  function comparisonReport(c) {
+    const taxa = list => list.length <= 10 ? list.join(', ') : `${list.slice(0, 10).join(', ')} and ${list.length - 10} more`;
+    const parts = [
+      `${c.names[0]} vs ${c.names[1]}, on ${c.sharedTaxa} shared taxa.`,
+      `Robinson–Foulds distance ${c.robinsonFoulds} (normalised ${c.normalisedRobinsonFoulds.toFixed(3)}).`,
+      c.branchScore != null
+        ? `Branch-score distance ${c.branchScore.toPrecision(4)}.`
+        : 'No branch-score distance: a tree has no branch lengths.',
+      c.quartetDistance != null
+        ? `Quartet distance ${c.quartetDistance} of ${c.quartets} quartets (${(100 * c.quartetDistance / c.quartets).toFixed(1)}%).`
+        : 'Quartet distance not computed: too many shared taxa.',
+    ];
+    if (c.onlyInFirst.length)  parts.push(`Only in ${c.names[0]} (${c.onlyInFirst.length}): ${taxa(c.onlyInFirst)}.`);
+    if (c.onlyInSecond.length) parts.push(`Only in ${c.names[1]} (${c.onlyInSecond.length}): ${taxa(c.onlyInSecond)}.`);
+    return parts.join(' ');
+  }
+
+  // ── UShER subtree ────────────────────────────────────────────────────────
+  // A whole pandemic-scale MAT is too big to browse, so ask for samples
+  // (defaulting to the current selection) and a neighbourhood size; Rust
+  // then asks for the .pb file and opens the subtree in a new window.
+  registry.setEnabled('open-usher-subtree', true);
+  registry.get('open-usher-subtree').exec = async () => {
+    const names = await app.showPromptDialog(
+      'Open UShER Subtree',
+      'Sample names to centre the subtree on (separated by commas or spaces):',
+      app.selectedTipNames.join(', '),
+    );
+    if (names == null) return;
+    const samples = names.split(/[\s,]+/).filter(Boolean);
+    if (samples.length === 0) {
+      app.showErrorDialog('Enter at least one sample name.');
+      return;
+    }
+    const answer = await app.showPromptDialog(
+      'Open UShER Subtree',
+      'Minimum number of tips around each sample:',
+      '500',
+    );
+    if (answer == null) return;
+    const size = parseInt(answer, 10);
+    if (!Number.isInteger(size) || size < 1) {
+      app.showErrorDialog('Enter a number of tips of at least 1.');
+      return;
+    }
+    try {
+      await invoke('open_usher_subtree', { samples, size });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  };
+
   // ── File picker: native Tauri dialog ───────────────────────────────────
   // WKWebView blocks <input type="file"> clicks from async contexts, so we
   // override the default pickFile with a Rust command.
@@ -48,7 +365,7 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
         invoke('new_window', { filePath: result.path })
           .catch(err => console.error('new_window failed:', err));
       } else {
-        await app.loadTree(result.content, result.name);
+        await loadTreeFromPath(result.path);
       }
     } catch (err) {
       app.showErrorDialog(err.message ?? String(err));
@@ -59,6 +376,108 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
   //    to the native file picker (same as Cmd+O).
   registry.get('open-tree').exec = () => app.pickFile();
 
+  // ── Tree export: Rust serialises the node table and writes the file ─────
+  app.setTreeWriter(async ({ tree, options, filename, filterName, extensions }) => {
+    try {
+      options = { ...options, branchLabels: _branchLabels };
+      await invoke('export_tree', { filename, filterName, extensions, tree, options });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  });
+
+  // ── Rooting: Rust re-roots the node table off the UI thread ─────────────
+  app.setRooter(({ tree, method, branchKeys }) => invoke('root_tree', { tree, method, branchKeys }));
+
+  // ── Time tree: Rust dates the tree under a strict clock by least squares and
+  //    opens it in a new window with the same date annotation on its axis.
+  //    A blank rate is estimated; a blank sequence length weights all
+  //    branches equally.
+  app.setDater(async ({ tree, dates, dateKey }) => {
+    const rateText = await app.showPromptDialog(
+      'Estimate Time Tree',
+      'Fixed clock rate in substitutions per site per year (leave blank to estimate it from the tip dates):',
+      '',
+    );
+    if (rateText == null) return;
+    const rate = rateText.trim() === '' ? null : parseFloat(rateText);
+    if (rate !== null && !(rate > 0)) {
+      app.showErrorDialog('Enter a positive clock rate, or leave it blank.');
+      return;
+    }
+    const lengthText = await app.showPromptDialog(
+      'Estimate Time Tree',
+      'Alignment length in sites, to weight branches by their variance (leave blank for equal weights):',
+      '',
+    );
+    if (lengthText == null) return;
+    const seqLength = lengthText.trim() === '' ? null : parseInt(lengthText, 10);
+    if (seqLength !== null && !(seqLength > 0)) {
+      app.showErrorDialog('Enter a positive alignment length, or leave it blank.');
+      return;
+    }
+    try {
+      const dated = await invoke('estimate_time_tree', { tree, dates, dateKey, options: { rate, seqLength } });
+      await app.showAlertDialog(
+        'Estimate Time Tree',
+        `Clock rate ${dated.rate.toExponential(3)} substitutions/site/year${rate === null ? ' (estimated)' : ''}; root date ${dated.rootDate.toFixed(2)}.`,
+      );
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  });
+
+  // ── RTT panel download: Rust fits the regression and writes CSV + summary ──
+  app.setRTTReportWriter(async ({ points, dateKey, filename }) => {
+    try {
+      await invoke('export_rtt_report', { filename, points, dateKey });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+    }
+  });
+
+  // ── Date-randomisation test: Rust refits the rate to permuted tip dates as a
+  //    cancellable background job.  Progress shows in the title bar and
+  //    Escape stops it, as for loads.
+  let _dateTestInFlight = false;
+  await currentWindow.listen(`date-test-progress-${currentWindow.label}`, (event) => {
+    const { done, total } = event.payload ?? {};
+    if (!_dateTestInFlight || !total) return;
+    document.title = `Date randomisation… ${Math.floor(100 * done / total)}% — PearTree`;
+  });
+  document.addEventListener('keydown', (e) => {
+    if (_dateTestInFlight && e.key === 'Escape') invoke('cancel_date_test').catch(() => {});
+  });
+  app.setRTTDateTester(async ({ points }) => {
+    const answer = await app.showPromptDialog(
+      'Date-Randomisation Test',
+      'Number of date permutations:',
+      '1000',
+    );
+    if (answer == null) return null;
+    const replicates = parseInt(answer, 10);
+    if (!Number.isInteger(replicates) || replicates < 1) {
+      app.showErrorDialog('Enter a positive number of permutations.');
+      return null;
+    }
+    const clustered = await app.showConfirmDialog(
+      'Date-Randomisation Test',
+      'Shuffle dates between clusters of tips sampled on the same date (recommended for outbreak data), or between single tips?',
+      { okLabel: 'Clustered', cancelLabel: 'By tip' },
+    );
+    const title = document.title;
+    _dateTestInFlight = true;
+    try {
+      return await invoke('date_randomisation_test', { points, options: { replicates, clustered } });
+    } catch (err) {
+      app.showErrorDialog(err.message ?? String(err));
+      return null;
+    } finally {
+      _dateTestInFlight = false;
+      document.title = title;
+    }
+  });
+
   // ── In Tauri, "Import Annotations" bypasses the modal picker phase and uses
   //    the native file dialog, then feeds the content straight into the config step.
   registry.get('import-annot').exec = async () => {
@@ -71,6 +490,19 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
     }
   };
 
+  // ─── Pending tree (new window opened for a tree computed in Rust) ─────────
+  try {
+    const pendingTree = await invoke('take_pending_tree');
+    if (pendingTree) {
+      app.closeModal();
+      document.getElementById('empty-state')?.classList.add('hidden');
+      await showParsedTree(pendingTree, pendingTree.trees[0].name ?? 'tree');
+    }
+  } catch (err) {
+    console.error('Failed to show pending tree:', err);
+    document.getElementById('empty-state')?.classList.remove('hidden');
+  }
+
   // ─── Pending file (new window opened for a specific file) ───────────────
   // When Rust creates a new window to open a file it stores the path
   // server-side keyed by window label. We retrieve and load it on startup.
@@ -78,13 +510,11 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
     const pending = await invoke('take_pending_file');
     if (pending) {
       try {
-        const content = await invoke('read_file_content', { path: pending });
-        const name = pending.split(/[\\/]/).pop() || 'tree';
         // Close modal if it was opened, hide empty state
         app.closeModal();
         const emptyState = document.getElementById('empty-state');
         if (emptyState) emptyState.classList.add('hidden');
-        await app.loadTree(content, name);
+        await loadTreeFromPath(pending);
       } catch (fileErr) {
         console.error('Failed to read pending file:', fileErr);
         // Ensure UI is in a recoverable state
@@ -122,9 +552,7 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
       app.closeModal();
       const emptyState = document.getElementById('empty-state');
       if (emptyState) emptyState.classList.add('hidden');
-      const content = await invoke('read_file_content', { path: filePath });
-      const name = filePath.split(/[\\/]/).pop() || 'tree';
-      await app.loadTree(content, name);
+      await loadTreeFromPath(filePath);
       // Open remaining files (if any) in new windows.
       for (let i = 1; i < paths.length; i++) {
         invoke('new_window', { filePath: paths[i] }).catch(err => console.error('new_window failed:', err));
@@ -155,9 +583,7 @@ import { setupTauriAdapter } from '@artic-network/pearcore/pearcore-tauri.js';
       const emptyState = document.getElementById('empty-state');
       if (emptyState) emptyState.classList.add('hidden');
       
-      const content = await invoke('read_file_content', { path: filePath });
-      const name = filePath.split(/[\\/]/).pop() || 'tree';
-      await app.loadTree(content, name);
+      await loadTreeFromPath(filePath);
     } catch (err) {
       // Restore empty state on error
       const emptyState = document.getElementById('empty-state');
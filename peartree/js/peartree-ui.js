@@ -745,8 +745,10 @@ function _buildCanvasContainer() {
       <button id="rtt-btn-download" class="btn btn-sm btn-outline-info" title="Download RTT data as CSV"><i class="bi bi-download"></i></button>
       <button id="rtt-btn-image" class="btn btn-sm btn-outline-warning" title="Export plot as image (SVG or PNG)"><i class="bi bi-image"></i></button>
       <button id="rtt-btn-stats" class="btn btn-sm btn-outline-secondary active" title="Show/hide statistics box"><i class="bi bi-info-circle"></i></button>
+      <button id="rtt-btn-datetest" class="btn btn-sm btn-outline-secondary" title="Date-randomisation test of temporal signal" style="display:none"><i class="bi bi-shuffle"></i></button>
     </div>
     <canvas id="rtt-canvas"></canvas>
+    <canvas id="rtt-datetest-canvas"></canvas>
   </div>
 </div>`;
 }
@@ -1,5 +1,5 @@
 import { parseNexus, parseNewick, graphToNewick, parseDelimited } from '@artic-network/pearcore/tree-io.js';
-import { computeLayoutFromGraph, graphVisibleTipCount, graphSubtreeHasHidden } from './tree-utils.js';
+import { computeLayoutFromGraph, graphVisibleTipCount, graphSubtreeHasHidden, graphToNodeTable, applyNodeTableRoot } from './tree-utils.js';
 import { fromNestedRoot, rerootOnGraph, reorderGraph, rotateNodeGraph, midpointRootGraph, temporalRootGraph, optimiseRootEdge, buildAnnotationSchema } from '@artic-network/pearcore/tree-graph.js';
 import { injectBuiltinStats, isNumericType, TreeCalibration, computeTemporalResiduals } from './phylograph.js';
 import { htmlEsc as _esc, downloadBlob as _downloadBlob, wireDropZone as _wireDropZone } from '@artic-network/pearcore/utils.js';
@@ -713,6 +713,9 @@ async function _initCore(root = document) {
   let graph              = null;  // PhyloGraph (adjacency-list model)
   let controlsBound      = false;
   let _cachedMidpoint      = null;  // cached midpointRootGraph() result; cleared on every tree change
+  let _rooter            = null;  // native rooting engine (set by platform adapters); null = JS graph functions
+  let _rootingSeq        = 0;     // bumped per reroot so a stale native result is dropped
+  let _dater             = null;  // native time-tree estimator (set by platform adapters); null = unavailable
   let isExplicitlyRooted = false; // true when root node carries annotations — rerooting disabled
   let _loadedFilename    = null;  // filename of the most recently loaded tree
   let _treeSourceUrl     = null;  // URL the current tree was fetched from (null if loaded from file)
@@ -3849,6 +3852,7 @@ async function _initCore(root = document) {
       }
       const _hasDate = axisDateAnnotEl.options.length > 1;
       axisDateAnnotEl.disabled  = !_hasDate;
+      commands.setEnabled('tree-time-tree', !!_dater && _hasDate && !schema.has('height'));
       // Restore the previous selection if it still exists; otherwise auto-select the
       // first available date annotation so the Calibrate control is never left blank
       // when date data has just been imported or parsed.
@@ -3877,10 +3881,14 @@ async function _initCore(root = document) {
 
   // ── Tree loading ──────────────────────────────────────────────────────────
 
+  // `text` is normally the raw file content.  Platform adapters with a native
+  // parser (peartree-tauri.js) may instead pass an already-parsed tree in the
+  // same shape as a parseNexus() entry: { root, peartreeSettings, taxonAnnotWarnings }.
   async function loadTree(text, filename) {
+    const preParsed = typeof text === 'string' ? null : text;
     // Normalise Windows CRLF and old Mac CR line endings to LF so that all
     // downstream parsers (parseNexus, parseNewick) receive clean input.
-    text = text.replace(/\r\n/g, '\n').replace(/\r/g, '\n');
+    if (!preParsed) text = text.replace(/\r\n/g, '\n').replace(/\r/g, '\n');
     // Close the RTT panel while the new tree loads (pin preference is preserved
     // so re-opening the panel will restore the pinned state).
     // Skip in fixed mode — the panel must stay visible at all times.
@@ -3903,7 +3911,7 @@ async function _initCore(root = document) {
       let parsedRoot = null;
 
       // Try NEXUS first; fall back to bare Newick
-      const nexusTrees = parseNexus(text);
+      const nexusTrees = preParsed ? [preParsed] : parseNexus(text);
       const _fileSettings = nexusTrees.length > 0 ? (nexusTrees[0].peartreeSettings || null) : null;
       if (nexusTrees.length > 0) {
         parsedRoot = nexusTrees[0].root;
@@ -4473,6 +4481,7 @@ async function _initCore(root = document) {
       // Show the date row whenever a tree is loaded; only hide if no usable annotations exist.
       const _hasDateAnnotations = axisDateAnnotEl.options.length > 1;
       axisDateAnnotEl.disabled  = !_hasDateAnnotations;
+      commands.setEnabled('tree-time-tree', !!_dater && _hasDateAnnotations && !_isTimedTree);
 
       // Restore date annotation (file settings take priority over saved prefs).
       // When no saved value is available, auto-select the first date annotation so the
@@ -4701,7 +4710,17 @@ async function _initCore(root = document) {
   /** Apply a reroot operation and refresh the layout. */
   function applyReroot(childNodeId, distFromParent) {
     if (!graph) return;
+    if (_rooter) {
+      _applyNativeRoot(rowOf => ({ kind: 'branch', node: rowOf.get(childNodeId), distance: distFromParent }));
+      return;
+    }
+    _rootingSeq++;
     rerootOnGraph(graph, childNodeId, distFromParent);
+    _afterReroot();
+  }
+
+  /** Refresh ordering, selection and layout after the graph has been re-rooted. */
+  function _afterReroot() {
     _cachedMidpoint = null;
     if (currentOrder === 'asc')  reorderGraph(graph, true);
     if (currentOrder === 'desc') reorderGraph(graph, false);
@@ -4723,9 +4742,38 @@ async function _initCore(root = document) {
     rttChart?.notifyLayoutChange?.();
   }
 
+  /**
+   * Re-root through the native rooting engine.  The graph is sent as a node
+   * table; methodFor(rowOf) builds the rooting method from its origId → row
+   * map.  The result is dropped if the tree was replaced or re-rooted again
+   * while the engine was running.
+   */
+  async function _applyNativeRoot(methodFor) {
+    const g   = graph;
+    const seq = ++_rootingSeq;
+    const branchKeys = [...(g.annotationSchema ?? [])]
+      .filter(([, def]) => def.isBranchAnnotation)
+      .map(([k]) => k);
+    const { tree, rowOf } = graphToNodeTable(g, branchKeys, () => undefined);
+    let result;
+    try {
+      result = await _rooter({ tree, method: methodFor(rowOf), branchKeys });
+    } catch (err) {
+      if (graph === g && seq === _rootingSeq) showErrorDialog(`Could not re-root the tree: ${err?.message ?? err}`);
+      return;
+    }
+    if (graph !== g || seq !== _rootingSeq) return;
+    applyNodeTableRoot(g, result.tree, result.source, rowOf, branchKeys);
+    _afterReroot();
+  }
+
   /** Apply a midpoint root and refresh the layout. */
   function applyMidpointRoot() {
     if (!graph) return;
+    if (_rooter) {
+      _applyNativeRoot(() => ({ kind: 'midpoint' }));
+      return;
+    }
     if (!_cachedMidpoint) _cachedMidpoint = midpointRootGraph(graph);
     const { childNodeId, distFromParent } = _cachedMidpoint;
     _cachedMidpoint = null;
@@ -4747,9 +4795,24 @@ async function _initCore(root = document) {
     return tipDates.size > 0 ? tipDates : null;
   }
 
+  /** Temporal rooting method for the native engine: tip dates and hidden nodes by row. */
+  function _nativeTemporalMethod(global, dates, rowOf) {
+    const rowDates = [];
+    for (const [id, dec] of dates ?? []) {
+      const row = rowOf.get(id);
+      if (row !== undefined) rowDates[row] = dec;
+    }
+    const hidden = [...graph.hiddenNodeIds].map(id => rowOf.get(id)).filter(row => row !== undefined);
+    return { kind: 'temporal', global, dates: Array.from(rowDates, d => d ?? null), hidden };
+  }
+
   function applyTemporalRoot() {
     if (!graph) return;
     const dates = _buildTipDates();  // null = isochronous (minimise root-to-tip variance)
+    if (_rooter) {
+      _applyNativeRoot(rowOf => _nativeTemporalMethod(false, dates, rowOf));
+      return;
+    }
     const { childNodeId, distFromParent } = optimiseRootEdge(graph, dates);
     applyReroot(childNodeId, distFromParent);
   }
@@ -4757,10 +4820,38 @@ async function _initCore(root = document) {
   function applyTemporalRootGlobal() {
     if (!graph) return;
     const dates = _buildTipDates();  // null = isochronous (minimise root-to-tip variance)
+    if (_rooter) {
+      _applyNativeRoot(rowOf => _nativeTemporalMethod(true, dates, rowOf));
+      return;
+    }
     const { childNodeId, distFromParent } = temporalRootGraph(graph, dates);
     applyReroot(childNodeId, distFromParent);
   }
 
+  /**
+   * Estimate a time tree from the tip dates of the current date annotation.
+   * The tree goes to the native dater as a node table with its annotations
+   * and a decimal year per dated tip row; the dater opens the result.
+   */
+  function applyTimeTree() {
+    if (!graph || !_dater) return;
+    const dateKey = axisDateAnnotEl.disabled ? null : (axisDateAnnotEl.value || null);
+    const dates   = _buildTipDates();
+    if (!dateKey || !dates) {
+      showErrorDialog('Choose a date annotation for the tips before estimating a time tree.');
+      return;
+    }
+    const keys = [...(graph.annotationSchema ?? new Map()).keys()].filter(k => !k.startsWith('__'));
+    const { tree, rowOf } = graphToNodeTable(graph, keys, gNode => gNode.name ?? gNode.origId);
+    const rowDates = new Array(tree.nodes.length).fill(null);
+    for (const [id, dec] of dates) {
+      const row = rowOf.get(id);
+      if (row !== undefined) rowDates[row] = dec;
+    }
+    tree.name = `${_loadedFilename ?? 'tree'} (time tree)`;
+    _dater({ tree, dates: rowDates, dateKey });
+  }
+
   // ── Control bindings (set up once after the first tree loads) ─────────────
 
   // Hoisted so loadTree can call it after restoring clade highlights.
@@ -5826,6 +5917,7 @@ async function _initCore(root = document) {
 
     btnTemporalRoot?.addEventListener('click', () => applyTemporalRoot());
     btnTemporalRootGlobal?.addEventListener('click', () => applyTemporalRootGlobal());
+    commands.get('tree-time-tree').exec = () => applyTimeTree();
 
     // ── Node Info (Cmd+I) ──────────────────────────────────────────────────
 
@@ -8022,6 +8114,12 @@ async function _initCore(root = document) {
     /** True when a tree is currently loaded in this window. */
     get hasTree() { return treeLoaded; },
 
+    /** Names of the currently selected tips (empty when none are selected). */
+    get selectedTipNames() {
+      if (!renderer?.nodeMap) return [];
+      return [...renderer._selectedTipIds].map(id => renderer.nodeMap.get(id)?.name).filter(Boolean);
+    },
+
     /** Trigger a file open. Default: click the hidden <input type="file">.
      *  Override with a platform-specific implementation (e.g. Tauri native dialog). */
     pickFile: () => fileInput.click(),
@@ -8040,6 +8138,24 @@ async function _initCore(root = document) {
      *  the Export Tree dialog.  Set to null to restore browser behaviour. */
     setExportSaveHandler:    exportCtrl.setExportSaveHandler,
 
+    /** Hand Newick/NEXUS export to a native writer for the current platform.
+     *  fn({ tree, options, filename, filterName, extensions }) — receives the
+     *  tree as a pre-order node table plus writer options instead of a
+     *  serialised string.  CSV export still uses the save handler. */
+    setTreeWriter:           exportCtrl.setTreeWriter,
+
+    /** Hand rerooting, midpoint and temporal rooting to a native engine.
+     *  fn({ tree, method, branchKeys }) → Promise<{ tree, source }> — receives
+     *  the tree as a pre-order node table and returns it re-rooted, with the
+     *  input row of each output row.  Set to null to root in JS. */
+    setRooter:               (fn) => { _rooter = fn; },
+
+    /** Enable Tree › Estimate Time Tree through a native dater.
+     *  fn({ tree, dates, dateKey }) — receives the tree as a pre-order node
+     *  table, the decimal-year date of each tip row (null if undated) and the
+     *  date annotation, and shows the time tree itself. */
+    setDater:                (fn) => { _dater = fn; },
+
     /** Override the theme-export save action for the current platform.
      *  fn({ content, filename, filterName, extensions }) — called instead of
      *  a browser download when the user clicks Export in the Theme section.
@@ -8061,6 +8177,19 @@ async function _initCore(root = document) {
      *  Same signature as setGraphicsSaveHandler. */
     setRTTImageSaveHandler:  (fn) => { rttChart.setImageSaveHandler(fn); },
 
+    /** Hand the RTT panel's CSV download to a native writer that runs the
+     *  regression itself and saves a CSV plus a summary report.
+     *  fn({ points, dateKey, filename }) — points are { name, date,
+     *  decimalDate, divergence }, one per tip.  Set to null to restore the
+     *  browser CSV download. */
+    setRTTReportWriter:      (fn) => { rttChart.setReportWriter(fn); },
+
+    /** Offer a date-randomisation test of temporal signal in the RTT panel.
+     *  fn({ points }) → Promise<{ rate, nullRates, pValue, nullLow, nullHigh,
+     *  clustered, clusters } | null> — asks for the options and runs the
+     *  test; the panel draws the null distribution under the plot. */
+    setRTTDateTester:        (fn) => { rttChart.setDateTester(fn); },
+
     /** Fetch a file by relative path, falling back to the absolute GitHub Pages
      *  URL if the relative fetch fails (e.g. file:// context). */
     fetchWithFallback,
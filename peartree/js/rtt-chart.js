@@ -78,8 +78,11 @@ export function createRTTChart({
   const btnClose    = panel.querySelector('#rtt-btn-close');
   const btnStats    = panel.querySelector('#rtt-btn-stats');
   const btnDownload = panel.querySelector('#rtt-btn-download');
+  const btnDateTest = panel.querySelector('#rtt-btn-datetest');
+  const dateTestCanvas = panel.querySelector('#rtt-datetest-canvas');
 
   btnDownload?.addEventListener('click', () => _downloadCSV());
+  btnDateTest?.addEventListener('click', () => _runDateTest());
 
   btnStats?.addEventListener('click', () => {
     rtt.statsBoxVisible = !rtt.statsBoxVisible;
@@ -117,12 +120,19 @@ export function createRTTChart({
 
   // ── CSV download ──────────────────────────────────────────────────────────
 
+  let _reportWriter = null;  // native CSV + summary writer (platform adapters); null = browser download
+
   function _downloadCSV() {
     const pts = _buildPoints();
     const cal = getCalibration();
     const fmt = getDateFormat();
     const reg = cal?.regression;
 
+    if (_reportWriter) {
+      _reportWriter({ points: _tipPoints(pts), dateKey: getDateAnnotKey() || null, filename: 'root-to-tip.csv' });
+      return;
+    }
+
     const header = ['name', 'date', 'date (decimal year)', 'divergence', 'regression', 'residual'];
     const rows = [header];
 
@@ -154,6 +164,109 @@ export function createRTTChart({
     downloadBlob(csvContent, 'text/csv', 'root-to-tip.csv');
   }
 
+  /** RTT points as sent to the native report writer and date tester. */
+  function _tipPoints(pts) {
+    const cal = getCalibration();
+    const fmt = getDateFormat();
+    return pts.map(pt => ({
+      name:        String(pt.name),
+      date:        pt.x != null ? (cal?.decYearToString(pt.x, 'full', fmt) ?? String(pt.x)) : null,
+      decimalDate: pt.x,
+      divergence:  pt.y,
+    }));
+  }
+
+  // ── Date-randomisation test ───────────────────────────────────────────────
+  // The tester (a platform adapter) asks for the options, runs the test and
+  // resolves with { rate, nullRates, pValue, nullLow, nullHigh, clustered,
+  // clusters }, or null if cancelled.  The null distribution is drawn as a
+  // histogram under the RTT plot until the tree or its dates change.
+
+  let _dateTester = null;
+  let _dateTest   = null;
+
+  async function _runDateTest() {
+    if (!_dateTester) return;
+    const points = _tipPoints(_buildPoints());
+    const result = await _dateTester({ points });
+    if (!result) return;
+    _dateTest = result;
+    panel.classList.add('has-datetest');
+    rtt._resize();
+    _drawDateTest();
+  }
+
+  function _clearDateTest() {
+    if (!_dateTest) return;
+    _dateTest = null;
+    panel.classList.remove('has-datetest');
+    rtt._resize();
+  }
+
+  function _drawDateTest() {
+    if (!_dateTest || !dateTestCanvas) return;
+    const dpr = window.devicePixelRatio || 1;
+    const w = dateTestCanvas.clientWidth, h = dateTestCanvas.clientHeight;
+    if (w < 1 || h < 1) return;
+    dateTestCanvas.width  = Math.round(w * dpr);
+    dateTestCanvas.height = Math.round(h * dpr);
+    const ctx = dateTestCanvas.getContext('2d');
+    ctx.setTransform(dpr, 0, 0, dpr, 0, 0);
+    ctx.clearRect(0, 0, w, h);
+
+    const { rate, nullRates, pValue, nullLow, nullHigh, clustered, clusters } = _dateTest;
+    const axisColor = getAxisColor?.() ?? '#f2f1e6';
+    const fontSize  = getAxisFontSize?.() ?? 9;
+    ctx.font = `${fontSize}px ${getAxisFontFamily?.() ?? 'sans-serif'}`;
+
+    const mL = 10, mR = 10, mT = fontSize * 2 + 10, mB = fontSize + 10;
+    const pw = w - mL - mR, ph = h - mT - mB;
+    let lo = Math.min(nullRates[0], rate), hi = Math.max(nullRates[nullRates.length - 1], rate);
+    if (hi <= lo) { lo -= 1e-9; hi += 1e-9; }
+    const pad = (hi - lo) * 0.04;
+    lo -= pad; hi += pad;
+    const xToS = v => mL + (v - lo) / (hi - lo) * pw;
+
+    const BINS = 40;
+    const counts = new Array(BINS).fill(0);
+    for (const r of nullRates) counts[Math.min(BINS - 1, Math.floor((r - lo) / (hi - lo) * BINS))]++;
+    const maxCount = Math.max(...counts);
+
+    // 95% interval of the null rates, then the histogram.
+    ctx.fillStyle = axisColor;
+    ctx.globalAlpha = 0.08;
+    ctx.fillRect(xToS(nullLow), mT, xToS(nullHigh) - xToS(nullLow), ph);
+    ctx.globalAlpha = 0.45;
+    const bw = pw / BINS;
+    counts.forEach((c, i) => {
+      if (!c) return;
+      const bh = c / maxCount * ph;
+      ctx.fillRect(mL + i * bw + 0.5, mT + ph - bh, Math.max(1, bw - 1), bh);
+    });
+    ctx.globalAlpha = 1;
+
+    // Baseline and the observed rate.
+    ctx.strokeStyle = axisColor;
+    ctx.globalAlpha = 0.5;
+    ctx.lineWidth = 1;
+    ctx.beginPath(); ctx.moveTo(mL, mT + ph + 0.5); ctx.lineTo(mL + pw, mT + ph + 0.5); ctx.stroke();
+    ctx.globalAlpha = 1;
+    ctx.strokeStyle = getRegressionColor?.() ?? '#19A699';
+    ctx.lineWidth = 2;
+    ctx.beginPath(); ctx.moveTo(xToS(rate), mT - 2); ctx.lineTo(xToS(rate), mT + ph); ctx.stroke();
+
+    ctx.fillStyle = axisColor;
+    ctx.textBaseline = 'top';
+    ctx.textAlign = 'left';
+    ctx.fillText(`Date randomisation: ${nullRates.length} permutations of ${clusters} ${clustered ? 'date clusters' : 'tips'}`, mL, 4);
+    ctx.fillText(`Rate ${rate.toExponential(3)} /yr · p = ${pValue < 0.001 ? '< 0.001' : pValue.toFixed(3)}`, mL, 6 + fontSize);
+    ctx.globalAlpha = 0.7;
+    ctx.fillText(lo.toExponential(2), mL, mT + ph + 4);
+    ctx.textAlign = 'right';
+    ctx.fillText(hi.toExponential(2), mL + pw, mT + ph + 4);
+    ctx.globalAlpha = 1;
+  }
+
   // ── Image export (SVG & PNG) — uses generic pearcore graphics exporter ────
 
   const _imgExporter = createGraphicsExporter({
@@ -778,10 +891,10 @@ export function createRTTChart({
     },
 
     /** Resize the canvas (call during window resize or panel-pin transitions). */
-    resize() { rtt._resize(); },
+    resize() { rtt._resize(); _drawDateTest(); },
 
     /** Call when the visible tip set or tree layout changes. */
-    notifyLayoutChange() { _update(); },
+    notifyLayoutChange() { _clearDateTest(); _update(); },
 
     /** Call when the tree renderer's style changes (theme, colours, etc.). */
     notifyStyleChange() {
@@ -810,9 +923,21 @@ export function createRTTChart({
     notifyCalibrationChange() { _update(); },
 
     /** Recompute OLS calibration and fire onCalibrationChange; also updates renderer if open. */
-    recomputeCalibration() { _update(); },
+    recomputeCalibration() { _clearDateTest(); _update(); },
 
     /** Override the image-save action for the current platform (e.g. Tauri native dialog). */
     setImageSaveHandler: _imgExporter.setSaveHandler,
+
+    /** Hand the CSV download to a native regression report writer.
+     *  fn({ points, dateKey, filename }) — points are { name, date, decimalDate, divergence }. */
+    setReportWriter(fn) { _reportWriter = fn; },
+
+    /** Enable the date-randomisation test button with a native tester.
+     *  fn({ points }) → Promise<result|null>; see _runDateTest. */
+    setDateTester(fn) {
+      _dateTester = fn;
+      if (btnDateTest) btnDateTest.style.display = fn ? '' : 'none';
+      if (!fn) _clearDateTest();
+    },
   };
 }
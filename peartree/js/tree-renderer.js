@@ -6,7 +6,8 @@ import { computeLayoutFromGraph } from './tree-utils.js';
 import { dateToDecimalYear, isNumericType, TreeCalibration } from './phylograph.js';
 import { getSequentialPalette, lerpSequential,
          DEFAULT_CATEGORICAL_PALETTE, DEFAULT_SEQUENTIAL_PALETTE,
-         MISSING_DATA_COLOUR, buildCategoricalColourMap } from '@artic-network/pearcore/palettes.js';
+         MISSING_DATA_COLOUR } from '@artic-network/pearcore/palettes.js';
+import { categoricalColourMap } from './legend-renderer.js';
 import { buildFont, TYPEFACES } from '@artic-network/pearcore/typefaces.js';
 import { CircleShapeRenderer }  from './shape-renderer.js';
 import { AnnotationLabelRenderer } from './label-renderer.js';
@@ -1356,7 +1357,8 @@ export class TreeRenderer {
    * Set (or clear) the palette to use for a specific annotation key.
    * Rebuilds any active colour scales that use that key.
    * @param {string}      key          Annotation name
-   * @param {string|null} paletteName  Name from CATEGORICAL_PALETTES or SEQUENTIAL_PALETTES, or null to revert to default
+   * @param {string|object|null} paletteName  Name from CATEGORICAL_PALETTES or SEQUENTIAL_PALETTES, a fixed
+   *                                   `{ value: colour }` map for categorical keys, or null to revert to default
    */
   setAnnotationPalette(key, paletteName) {
     if (paletteName) {
@@ -1436,7 +1438,7 @@ export class TreeRenderer {
     const scale = new Map();
     if (def.dataType === 'categorical' || def.dataType === 'ordinal') {
       const paletteName = this._annotationPaletteOverrides.get(key);
-      const colourMap = buildCategoricalColourMap(def.values || [], paletteName);
+      const colourMap = categoricalColourMap(def.values || [], paletteName);
       for (const [v, c] of colourMap) scale.set(v, c);
     } else if (def.dataType === 'date') {
       // Sequential scale; values are ISO date strings converted to decimal years at draw time.
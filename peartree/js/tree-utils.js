@@ -465,3 +465,85 @@ export function graphSubtreeHasHidden(graph, gStartIdx, gFromIdx) {
 
 
 
+
+/**
+ * Flatten a PhyloGraph into the pre-order node table read by the native tree
+ * writer: { rooted: true, nodes: [{ parent, length?, label?, annotations }] }
+ * with the root at row 0.  A root lying on an edge becomes a virtual root row.
+ * Tip labels come from tipNameFn(gnode); only annotations named in `annotKeys`
+ * are copied.  Returns { tree, rowOf } where rowOf maps origId → row.
+ */
+export function graphToNodeTable(graph, annotKeys, tipNameFn) {
+  const nodes = [];
+  const rowOf = new Map();
+  const { nodeA, nodeB, lenA, lenB } = graph.root;
+  const stack = [];
+  if (lenA === 0) {
+    stack.push({ ni: nodeA, fi: -1, parent: null, length: null });
+  } else {
+    nodes.push({ parent: null, annotations: {} });
+    stack.push({ ni: nodeB, fi: nodeA, parent: 0, length: lenB });
+    stack.push({ ni: nodeA, fi: nodeB, parent: 0, length: lenA });
+  }
+  while (stack.length) {
+    const { ni, fi, parent, length } = stack.pop();
+    const gnode = graph.nodes[ni];
+    const row = nodes.length;
+    const annotations = {};
+    for (const k of annotKeys) {
+      if (gnode.annotations?.[k] != null) annotations[k] = gnode.annotations[k];
+    }
+    const entry = { parent, annotations };
+    if (Number.isFinite(length)) entry.length = length;
+    const children = gnode.adjacents.filter(a => a !== fi);
+    if (children.length === 0) entry.label = tipNameFn(gnode);
+    nodes.push(entry);
+    rowOf.set(gnode.origId, row);
+    for (let k = children.length - 1; k >= 0; k--) {
+      const c = children[k];
+      stack.push({ ni: c, fi: ni, parent: row, length: graph.nodes[c].lengths[0] });
+    }
+  }
+  return { tree: { rooted: true, nodes }, rowOf };
+}
+
+/**
+ * Re-root a PhyloGraph in place to match a node table re-rooted by the native
+ * rooting engine.  `tree` is the re-rooted table (virtual root at row 0),
+ * `source[row]` the row of the table sent in that each row came from, and
+ * `rowOf` the origId → row map graphToNodeTable returned for that table.
+ * Each node's new parent is swapped into adjacents[0] and the annotations
+ * named in `branchKeys` are taken from the table, as rerootOnGraph leaves them.
+ */
+export function applyNodeTableRoot(graph, tree, source, rowOf, branchKeys) {
+  const origIdOfRow = [];
+  for (const [origId, row] of rowOf) origIdOfRow[row] = origId;
+  const idxOf = source.map(row => row == null ? -1 : graph.origIdToIdx.get(origIdOfRow[row]));
+  const rootKids = [];
+  for (let row = 1; row < tree.nodes.length; row++) {
+    if (tree.nodes[row].parent === 0) rootKids.push(row);
+  }
+  const [rowA, rowB] = rootKids;
+
+  for (let row = 1; row < tree.nodes.length; row++) {
+    const { parent, annotations } = tree.nodes[row];
+    const gnode = graph.nodes[idxOf[row]];
+    // The root's two children are each other's parent, across the root edge.
+    const parentIdx = parent !== 0 ? idxOf[parent] : idxOf[row === rowA ? rowB : rowA];
+    const pos = gnode.adjacents.indexOf(parentIdx);
+    if (pos > 0) {
+      [gnode.adjacents[0], gnode.adjacents[pos]] = [gnode.adjacents[pos], gnode.adjacents[0]];
+      [gnode.lengths[0],   gnode.lengths[pos]]   = [gnode.lengths[pos],   gnode.lengths[0]];
+    }
+    for (const k of branchKeys) {
+      if (annotations?.[k] != null) gnode.annotations[k] = annotations[k];
+      else delete gnode.annotations[k];
+    }
+  }
+
+  graph.root = {
+    nodeA: idxOf[rowA], nodeB: idxOf[rowB],
+    lenA: tree.nodes[rowA].length ?? 0, lenB: tree.nodes[rowB].length ?? 0,
+    annotations: {},
+  };
+}
@@ -0,0 +1,496 @@
+//! Nextstrain Auspice v2 JSON reader and writer.
+//!
+//! Every entry of `tree` (an object, or an array of them for multi-tree
+//! datasets) becomes a `Tree`. Branch lengths come from the cumulative
+//! divergence `node_attrs.div`, or from `num_date` for time-only datasets.
+//!
+//! Each other node attribute `x` (`{value, confidence, entropy}`) gives:
+//! `x` (the value); `x_confidence` for a numeric interval; `x.prob`, `x.set`
+//! and `x.set.prob` (as for MCC trees) for a categorical one; and
+//! `x_entropy`. On branches, `mutations.GENE` becomes `GENE_mutations` and
+//! `labels.k` becomes `k_label`.
+//!
+//! Colourings with an explicit scale become per-value colour maps in
+//! `annotationPalettes`, and `display_defaults.color_by` colours the tips,
+//! both returned as PearTree settings. The `k_label` names read from branch
+//! labels are returned too, as `auspiceBranchLabels`.
+//!
+//! The writer maps these back: `x_confidence`, `x_95%_HPD` and `x.set` /
+//! `x.set.prob` become the confidence of attribute `x`, `GENE_mutations` and
+//! the branch labels in `WriteOptions::branch_labels` return to
+//! `branch_attrs`, and cumulative branch lengths become
+//! `div`. Every exported attribute gets a colouring, with fixed palettes and
+//! `user_colour` written as explicit scales.
+
+use crate::newick::ParseError;
+use crate::tree::{Tree, TreeNode};
+use crate::writer::{children_of, display_label, WriteOptions};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, Write};
+
+/// Returns true when `text` looks like an Auspice dataset: a JSON object
+/// with `meta` and `tree` members.
+pub fn is_auspice(text: &str) -> bool {
+    text.trim_start_matches('\u{feff}').trim_start().starts_with('{')
+        && text.contains("\"meta\"")
+        && text.contains("\"tree\"")
+}
+
+/// Parses JSON with no nesting limit, growing the stack as needed, since
+/// nested-children trees are as deep as the tree itself.
+pub(crate) fn parse_json(text: &str) -> Result<Value, ParseError> {
+    let mut de = serde_json::Deserializer::from_str(text);
+    de.disable_recursion_limit();
+    let json_error = |e: serde_json::Error| ParseError::at_line(text, e.line(), e.column(), e.to_string());
+    let value = Value::deserialize(serde_stacker::Deserializer::new(&mut de)).map_err(json_error)?;
+    de.end().map_err(json_error)?;
+    Ok(value)
+}
+
+/// Reads the trees and the settings derived from `meta`.
+pub fn parse_auspice(text: &str) -> Result<(Vec<Tree>, Option<Value>), ParseError> {
+    let mut doc = parse_json(text)?;
+    let roots = match doc.get_mut("tree").map(Value::take) {
+        Some(Value::Array(roots)) => roots,
+        Some(root @ Value::Object(_)) => vec![root],
+        _ => return Err(ParseError::new(0, "Auspice JSON has no \"tree\" object")),
+    };
+    let meta = doc.get("meta");
+    let title = meta.and_then(|m| m.get("title")).and_then(Value::as_str);
+    let mut branch_labels = BTreeSet::new();
+    let trees = roots
+        .into_iter()
+        .map(|root| Tree { name: title.map(str::to_string), ..read_tree(root, &mut branch_labels) })
+        .collect();
+    Ok((trees, settings_from_meta(meta, branch_labels)))
+}
+
+/// Builds the node table from the nested `children` arrays. Each node is
+/// taken apart as it is visited, so the `Value` tree is never dropped
+/// recursively. Names of branch-label annotations are added to `branch_labels`.
+fn read_tree(root: Value, branch_labels: &mut BTreeSet<String>) -> Tree {
+    let mut tree = Tree { rooted: Some(true), ..Default::default() };
+    let mut div = Vec::new();
+    let mut dates = Vec::new();
+    let mut stack = vec![(root, None)];
+    while let Some((mut node, parent)) = stack.pop() {
+        let row = tree.push_node(parent);
+        let children = node.get_mut("children").map(Value::take);
+        let (d, t) = read_node(node, &mut tree.nodes[row], branch_labels);
+        div.push(d);
+        dates.push(t);
+        if let Some(Value::Array(children)) = children {
+            for child in children.into_iter().rev() {
+                stack.push((child, Some(row)));
+            }
+        }
+    }
+    let positions = [div, dates]
+        .into_iter()
+        .find_map(|xs| xs.into_iter().collect::<Option<Vec<f64>>>());
+    if let Some(xs) = positions {
+        for n in 0..tree.nodes.len() {
+            tree.nodes[n].length = tree.nodes[n].parent.map(|p| xs[n] - xs[p]);
+        }
+    }
+    tree
+}
+
+/// Fills in `out` from one Auspice node, returning its `div` and `num_date`.
+fn read_node(node: Value, out: &mut TreeNode, branch_labels: &mut BTreeSet<String>) -> (Option<f64>, Option<f64>) {
+    let Value::Object(mut node) = node else { return (None, None) };
+    out.label = node.get("name").and_then(Value::as_str).map(str::to_string);
+    let annots = &mut out.annotations;
+    let mut div = None;
+    let mut date = None;
+    if let Some(Value::Object(attrs)) = node.remove("node_attrs") {
+        for (key, attr) in attrs {
+            match attr {
+                Value::Number(x) if key == "div" => div = x.as_f64(),
+                Value::Object(mut attr) => {
+                    if let Some(value) = attr.remove("value") {
+                        if key == "num_date" {
+                            date = value.as_f64();
+                        }
+                        match attr.remove("confidence") {
+                            Some(ci @ Value::Array(_)) => {
+                                annots.insert(format!("{key}_confidence"), ci);
+                            }
+                            Some(Value::Object(probs)) => summarise_confidence(annots, &key, &value, probs),
+                            _ => {}
+                        }
+                        annots.insert(key.clone(), value);
+                    }
+                    if let Some(entropy) = attr.remove("entropy") {
+                        annots.insert(format!("{key}_entropy"), entropy);
+                    }
+                }
+                other => {
+                    annots.insert(key, other);
+                }
+            }
+        }
+    }
+    if let Some(Value::Object(branch)) = node.remove("branch_attrs") {
+        if let Some(Value::Object(mutations)) = branch.get("mutations") {
+            for (gene, list) in mutations {
+                if list.as_array().is_some_and(|l| !l.is_empty()) {
+                    annots.insert(format!("{gene}_mutations"), list.clone());
+                }
+            }
+        }
+        if let Some(Value::Object(labels)) = branch.get("labels") {
+            for (k, label) in labels {
+                let key = format!("{k}_label");
+                branch_labels.insert(key.clone());
+                annots.insert(key, label.clone());
+            }
+        }
+    }
+    (div, date)
+}
+
+/// Writes a categorical attribute's confidence (`{value: probability}`) as
+/// `key.prob`, `key.set` and `key.set.prob`, most probable first.
+fn summarise_confidence(annots: &mut Map<String, Value>, key: &str, value: &Value, probs: Map<String, Value>) {
+    let mut ranked: Vec<(String, f64)> = probs.into_iter().filter_map(|(v, p)| Some((v, p.as_f64()?))).collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    if let Some(&(_, p)) = value.as_str().and_then(|v| ranked.iter().find(|(r, _)| r == v)) {
+        annots.insert(format!("{key}.prob"), Value::from(p));
+    }
+    annots.insert(format!("{key}.set"), ranked.iter().map(|(v, _)| Value::from(v.as_str())).collect());
+    annots.insert(format!("{key}.set.prob"), ranked.iter().map(|&(_, p)| Value::from(p)).collect());
+}
+
+/// PearTree settings seeded from `meta`: fixed colour maps for colourings
+/// with a scale, and the default colouring as the tip colour. The names of
+/// the branch-label annotations go in `auspiceBranchLabels`, so an export
+/// can tell them from node attributes that merely end in `_label`.
+fn settings_from_meta(meta: Option<&Value>, branch_labels: BTreeSet<String>) -> Option<Value> {
+    let mut palettes = Map::new();
+    for coloring in meta.and_then(|m| m.get("colorings")).and_then(Value::as_array).into_iter().flatten() {
+        let (Some(key), Some(scale)) = (coloring.get("key").and_then(Value::as_str), coloring.get("scale")) else {
+            continue;
+        };
+        if coloring.get("type").and_then(Value::as_str) == Some("continuous") {
+            continue;
+        }
+        let colours: Map<String, Value> = scale
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let value = match entry.get(0)? {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                Some((value, entry.get(1)?.clone()))
+            })
+            .collect();
+        if !colours.is_empty() {
+            palettes.insert(key.to_string(), Value::Object(colours));
+        }
+    }
+    let mut settings = Map::new();
+    if !palettes.is_empty() {
+        settings.insert("annotationPalettes".into(), Value::Object(palettes));
+    }
+    let color_by = meta.and_then(|m| m.pointer("/display_defaults/color_by")).and_then(Value::as_str);
+    if let Some(key) = color_by.filter(|&k| k != "none") {
+        settings.insert("tipColourBy".into(), Value::from(key));
+        settings.insert("legendAnnotation".into(), Value::from(key));
+    }
+    if !branch_labels.is_empty() {
+        settings.insert("auspiceBranchLabels".into(), branch_labels.into_iter().collect());
+    }
+    (!settings.is_empty()).then_some(Value::Object(settings))
+}
+
+enum Step {
+    Open(usize),
+    Separator,
+    Close,
+}
+
+/// Writes the subtree at `root` as an Auspice v2 dataset.
+pub fn write_auspice(out: &mut impl Write, tree: &Tree, root: usize, opts: &WriteOptions) -> io::Result<()> {
+    let children = children_of(tree);
+    let mut order = Vec::new();
+    let mut pending = vec![root];
+    while let Some(n) = pending.pop() {
+        order.push(n);
+        pending.extend(children[n].iter().rev());
+    }
+    let has_lengths = order.iter().any(|&n| n != root && tree.nodes[n].length.is_some());
+    let mut div = vec![0.0; tree.nodes.len()];
+    for &n in &order[1..] {
+        let p = tree.nodes[n].parent.unwrap_or(root);
+        div[n] = div[p] + tree.nodes[n].length.unwrap_or(0.0);
+    }
+
+    let meta = meta_for(tree, &order, opts);
+    write!(out, r#"{{"version":"v2","meta":"#)?;
+    serde_json::to_writer(&mut *out, &meta)?;
+    out.write_all(br#","tree":"#)?;
+    let mut used: HashSet<String> = order
+        .iter()
+        .filter(|&&n| children[n].is_empty())
+        .filter_map(|&n| display_label(tree, n, true, opts))
+        .collect();
+    let mut next_id = 0;
+    let mut stack = vec![Step::Open(root)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Open(n) => {
+                let is_tip = children[n].is_empty();
+                let name = if is_tip {
+                    display_label(tree, n, true, opts).unwrap_or_default()
+                } else {
+                    match tree.nodes[n].label.clone().filter(|l| used.insert(l.clone())) {
+                        Some(label) => label,
+                        None => loop {
+                            next_id += 1;
+                            let id = format!("NODE_{next_id:07}");
+                            if used.insert(id.clone()) {
+                                break id;
+                            }
+                        },
+                    }
+                };
+                let (mut node_attrs, branch_attrs) = attrs_for(&tree.nodes[n], opts);
+                if has_lengths {
+                    node_attrs.insert("div".into(), Value::from(div[n]));
+                }
+                out.write_all(br#"{"name":"#)?;
+                serde_json::to_writer(&mut *out, &name)?;
+                out.write_all(br#","node_attrs":"#)?;
+                serde_json::to_writer(&mut *out, &node_attrs)?;
+                if !branch_attrs.is_empty() {
+                    out.write_all(br#","branch_attrs":"#)?;
+                    serde_json::to_writer(&mut *out, &branch_attrs)?;
+                }
+                if is_tip {
+                    out.write_all(b"}")?;
+                    continue;
+                }
+                out.write_all(br#","children":["#)?;
+                stack.push(Step::Close);
+                for (k, &c) in children[n].iter().enumerate().rev() {
+                    stack.push(Step::Open(c));
+                    if k > 0 {
+                        stack.push(Step::Separator);
+                    }
+                }
+            }
+            Step::Separator => out.write_all(b",")?,
+            Step::Close => out.write_all(b"]}")?,
+        }
+    }
+    writeln!(out, "}}")
+}
+
+/// Splits an annotation key into the attribute it qualifies and what it
+/// adds to it, for keys the reader derives from Auspice attributes.
+fn qualifier(key: &str) -> Option<(&str, &'static str)> {
+    [("_confidence", "confidence"), ("_95%_HPD", "confidence"), ("_entropy", "entropy"), (".set.prob", "probs"), (".set", "set"), (".prob", "prob")]
+        .into_iter()
+        .find_map(|(suffix, kind)| Some((key.strip_suffix(suffix)?, kind)))
+}
+
+/// The `node_attrs` and `branch_attrs` for one node.
+fn attrs_for(node: &TreeNode, opts: &WriteOptions) -> (Map<String, Value>, Map<String, Value>) {
+    let get = |key: &str| node.annotations.get(key).filter(|v| !v.is_null());
+    let exported = |key: &str| opts.annotations.iter().any(|k| k == key) && get(key).is_some();
+    let mut node_attrs = Map::new();
+    let mut mutations = Map::new();
+    let mut labels = Map::new();
+    for key in &opts.annotations {
+        let Some(value) = get(key) else { continue };
+        if let Some(gene) = key.strip_suffix("_mutations").filter(|_| value.is_array()) {
+            mutations.insert(gene.to_string(), value.clone());
+            continue;
+        }
+        if let Some(k) = key.strip_suffix("_label").filter(|_| opts.branch_labels.contains(key)) {
+            labels.insert(k.to_string(), value.clone());
+            continue;
+        }
+        if qualifier(key).is_some_and(|(base, _)| exported(base)) {
+            continue;
+        }
+        let qualified = |suffix: &str| {
+            let k = format!("{key}{suffix}");
+            if exported(&k) { get(&k) } else { None }
+        };
+        let mut attr = Map::new();
+        attr.insert("value".into(), value.clone());
+        if let Some(ci) = qualified("_confidence").or_else(|| qualified("_95%_HPD")) {
+            attr.insert("confidence".into(), ci.clone());
+        }
+        if let (Some(Value::Array(set)), Some(Value::Array(probs))) = (qualified(".set"), get(&format!("{key}.set.prob"))) {
+            let probs: Map<String, Value> = set
+                .iter()
+                .zip(probs)
+                .map(|(v, p)| (v.as_str().map_or_else(|| v.to_string(), str::to_string), p.clone()))
+                .collect();
+            attr.insert("confidence".into(), Value::Object(probs));
+        }
+        if let Some(entropy) = qualified("_entropy") {
+            attr.insert("entropy".into(), entropy.clone());
+        }
+        node_attrs.insert(key.clone(), Value::Object(attr));
+    }
+    let mut branch_attrs = Map::new();
+    if !mutations.is_empty() {
+        branch_attrs.insert("mutations".into(), Value::Object(mutations));
+    }
+    if !labels.is_empty() {
+        branch_attrs.insert("labels".into(), Value::Object(labels));
+    }
+    (node_attrs, branch_attrs)
+}
+
+/// The `meta` block: a colouring for every attribute with scalar values,
+/// typed from the values present, and the tip colouring as `color_by`.
+fn meta_for(tree: &Tree, order: &[usize], opts: &WriteOptions) -> Value {
+    let palettes = opts.settings.as_ref().and_then(|s| s.get("annotationPalettes"));
+    let mut colorings = Vec::new();
+    for key in &opts.annotations {
+        if key.ends_with("_mutations") || opts.branch_labels.contains(key) {
+            continue;
+        }
+        if qualifier(key).is_some_and(|(base, _)| opts.annotations.iter().any(|k| k == base)) {
+            continue;
+        }
+        let values: Vec<&Value> =
+            order.iter().filter_map(|&n| tree.nodes[n].annotations.get(key)).filter(|v| !v.is_null()).collect();
+        let kind = if key == "num_date" {
+            "temporal"
+        } else if values.iter().all(|v| v.is_number()) {
+            "continuous"
+        } else if values.iter().all(|v| v.is_boolean()) {
+            "boolean"
+        } else if values.iter().all(|v| v.is_string()) {
+            "categorical"
+        } else {
+            continue;
+        };
+        if values.is_empty() {
+            continue;
+        }
+        let mut coloring = json!({ "key": key, "title": if key == "num_date" { "Date" } else { key }, "type": kind });
+        let scale: Vec<Value> = if key == "user_colour" {
+            let mut seen = HashSet::new();
+            values.iter().filter_map(|v| v.as_str()).filter(|c| seen.insert(*c)).map(|c| json!([c, c])).collect()
+        } else if kind == "categorical" {
+            let fixed = palettes.and_then(|p| p.get(key)).and_then(Value::as_object);
+            fixed.into_iter().flatten().map(|(v, c)| json!([v, c])).collect()
+        } else {
+            Vec::new()
+        };
+        if !scale.is_empty() {
+            coloring["scale"] = Value::Array(scale);
+        }
+        colorings.push(coloring);
+    }
+    let color_by = opts.settings.as_ref().and_then(|s| s.get("tipColourBy")).and_then(Value::as_str);
+    let color_by = color_by.filter(|&k| colorings.iter().any(|c| c["key"] == k));
+    let mut meta = json!({ "panels": ["tree"], "colorings": colorings });
+    if let Some(name) = &tree.name {
+        meta["title"] = Value::from(name.as_str());
+    }
+    if let Some(key) = color_by {
+        meta["display_defaults"] = json!({ "color_by": key });
+    }
+    meta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::Format;
+
+    /// `((a,b),c)` with categorical, temporal, mutation and label annotations.
+    fn sample() -> Tree {
+        let mut tree = Tree { name: Some("ncov".into()), rooted: Some(true), ..Default::default() };
+        let root = tree.push_node(None);
+        let inner = tree.push_node(Some(root));
+        tree.nodes[inner].length = Some(0.5);
+        tree.nodes[inner].annotations.insert("aa_label".into(), json!("S: N501Y"));
+        tree.nodes[inner].annotations.insert("S_mutations".into(), json!(["N501Y"]));
+        for (parent, label, country) in [(inner, "a", "UK"), (inner, "b", "US"), (root, "c", "UK")] {
+            let n = tree.push_node(Some(parent));
+            let node = &mut tree.nodes[n];
+            node.label = Some(label.into());
+            node.length = Some(1.5);
+            node.annotations.insert("country".into(), json!(country));
+            node.annotations.insert("clade_label".into(), json!("20I"));
+            node.annotations.insert("num_date".into(), json!(2021.25));
+            node.annotations.insert("num_date_confidence".into(), json!([2021.0, 2021.5]));
+        }
+        tree
+    }
+
+    fn write(tree: &Tree, opts: &WriteOptions) -> String {
+        let mut out = Vec::new();
+        write_auspice(&mut out, tree, 0, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn round_trips_attributes_branch_labels_and_settings() {
+        let tree = sample();
+        let opts = WriteOptions {
+            format: Format::Auspice,
+            annotations: ["country", "clade_label", "num_date", "num_date_confidence", "aa_label", "S_mutations"]
+                .map(String::from)
+                .to_vec(),
+            settings: Some(json!({ "tipColourBy": "country", "annotationPalettes": { "country": { "UK": "#ff0000" } } })),
+            branch_labels: vec!["aa_label".into()],
+            ..Default::default()
+        };
+        let text = write(&tree, &opts);
+        assert!(is_auspice(&text));
+        let (mut trees, settings) = parse_auspice(&text).unwrap();
+        let back = trees.remove(0);
+        assert_eq!(back.name, tree.name);
+        assert_eq!(back.nodes.len(), tree.nodes.len());
+        for (a, b) in tree.nodes.iter().zip(&back.nodes) {
+            assert_eq!((a.parent, a.length, &a.annotations), (b.parent, b.length, &b.annotations));
+        }
+        let tips: Vec<_> = back.tips().iter().map(|&t| back.nodes[t].label.as_deref()).collect();
+        assert_eq!(tips, [Some("a"), Some("b"), Some("c")]);
+        let settings = settings.unwrap();
+        assert_eq!(settings["tipColourBy"], json!("country"));
+        assert_eq!(settings["annotationPalettes"]["country"]["UK"], json!("#ff0000"));
+        assert_eq!(settings["auspiceBranchLabels"], json!(["aa_label"]));
+    }
+
+    #[test]
+    fn keeps_other_label_annotations_as_node_attributes() {
+        let opts = WriteOptions { annotations: vec!["clade_label".into()], ..Default::default() };
+        let doc: Value = serde_json::from_str(&write(&sample(), &opts)).unwrap();
+        let tip = &doc["tree"]["children"][1];
+        assert_eq!(tip["node_attrs"]["clade_label"]["value"], json!("20I"));
+        assert!(tip.get("branch_attrs").is_none());
+    }
+
+    #[test]
+    fn summarises_categorical_confidence() {
+        let text = r#"{"meta":{},"tree":{"name":"r","children":[{"name":"a","node_attrs":
+            {"host":{"value":"bat","confidence":{"bat":0.75,"rat":0.25}}}},{"name":"b"}]}}"#;
+        let tree = parse_auspice(text).unwrap().0.remove(0);
+        let annots = &tree.nodes[1].annotations;
+        assert_eq!(annots["host.prob"], json!(0.75));
+        assert_eq!(annots["host.set"], json!(["bat", "rat"]));
+        assert_eq!(annots["host.set.prob"], json!([0.75, 0.25]));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [r#"{"meta":{},"tree":"#, r#"{"meta":{}}"#, r#"{"meta":{},"tree":3}"#, r#"{"meta":{},"tree":{}} x"#] {
+            assert!(parse_auspice(text).is_err(), "{text:?} parsed");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Clades as tip bitsets, shared by the tree-set summaries (consensus, MCC).
+//!
+//! The taxon set is fixed by the first tree read; every later tree must have
+//! exactly the same tip labels. Trees are read as rooted, so each node's
+//! clade is the set of tips below it.
+
+use crate::tree::Tree;
+use std::collections::HashMap;
+
+/// Set of tips, one bit per taxon.
+pub type Clade = Vec<u64>;
+
+/// Tip labels in the order of the first tree, with a reverse lookup.
+pub struct Taxa {
+    pub names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Taxa {
+    /// Takes the taxon set from `tree` (tree number `i`, for messages).
+    pub fn from_tree(tree: &Tree, i: usize) -> Result<Self, String> {
+        let mut taxa = Taxa { names: Vec::new(), index: HashMap::new() };
+        for t in tree.tips() {
+            let label = tree.nodes[t].label.clone().unwrap_or_default();
+            if taxa.index.insert(label.clone(), taxa.names.len()).is_some() {
+                return Err(format!("tree {} has more than one tip named '{label}'", i + 1));
+            }
+            taxa.names.push(label);
+        }
+        if taxa.names.len() < 2 {
+            return Err("trees need at least two tips to be summarised".to_string());
+        }
+        Ok(taxa)
+    }
+
+    /// The clade of every node of `tree` (tree number `i`), by row.
+    pub fn clades(&self, tree: &Tree, i: usize) -> Result<Vec<Clade>, String> {
+        let words = self.names.len().div_ceil(64);
+        let mut bits = vec![vec![0u64; words]; tree.nodes.len()];
+        let tips = tree.tips();
+        if tips.len() != self.names.len() {
+            return Err(format!("tree {} has {} tips but the first tree has {}", i + 1, tips.len(), self.names.len()));
+        }
+        for &t in &tips {
+            let label = tree.nodes[t].label.as_deref().unwrap_or_default();
+            let &k = self
+                .index
+                .get(label)
+                .ok_or_else(|| format!("tree {} has tip '{label}', which is not in the first tree", i + 1))?;
+            bits[t][k / 64] |= 1 << (k % 64);
+        }
+        // Parents precede children in the node table, so a reverse pass sees
+        // every child before its parent.
+        for n in (0..tree.nodes.len()).rev() {
+            if let Some(p) = tree.nodes[n].parent {
+                let (head, tail) = bits.split_at_mut(n);
+                for (a, b) in head[p].iter_mut().zip(&tail[0]) {
+                    *a |= b;
+                }
+            }
+        }
+        Ok(bits)
+    }
+}
+
+pub fn is_subset(a: &Clade, b: &Clade) -> bool {
+    a.iter().zip(b).all(|(x, y)| x & !y == 0)
+}
+
+pub fn is_disjoint(a: &Clade, b: &Clade) -> bool {
+    a.iter().zip(b).all(|(x, y)| x & y == 0)
+}
+
+/// Number of tips in the clade.
+pub fn size(c: &Clade) -> u32 {
+    c.iter().map(|w| w.count_ones()).sum()
+}
+
+/// Index of the lowest-numbered taxon in the clade.
+pub fn first_taxon(c: &Clade) -> usize {
+    c.iter()
+        .enumerate()
+        .find(|(_, w)| **w != 0)
+        .map(|(i, w)| i * 64 + w.trailing_zeros() as usize)
+        .unwrap_or(0)
+}
+
+pub fn contains(c: &Clade, taxon: usize) -> bool {
+    c[taxon / 64] >> (taxon % 64) & 1 == 1
+}
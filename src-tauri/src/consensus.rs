@@ -0,0 +1,206 @@
+//! Consensus trees from a set of trees (posterior sample or bootstrap replicates).
+//!
+//! Trees are read as rooted: each internal node defines a clade, the set of
+//! tips below it. Clades are counted across the set, and those found in at
+//! least `threshold` of the trees are assembled, most frequent first, into a
+//! consensus tree. At 0.5 a clade must be in more than half the trees, which
+//! gives the majority-rule tree: two such clades are always compatible. A
+//! clade that conflicts with one already accepted is skipped, so thresholds
+//! below 0.5 give a greedy consensus rather than an invalid tree.
+//!
+//! Each internal node of the result carries a `clade_frequency` annotation
+//! (0–1); branch lengths are the mean length of that clade's branch over the
+//! trees containing it.
+
+use crate::clades::{self, Clade, Taxa};
+use crate::tree::Tree;
+use crate::treeset::TreeSet;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How often a clade was seen and the total length of its branch.
+#[derive(Default)]
+struct CladeStats {
+    count: usize,
+    length_sum: f64,
+    length_count: usize,
+    /// Last tree counted, so a clade repeated by unary nodes counts once.
+    last_tree: Option<usize>,
+}
+
+/// Counts clades across trees `burnin..` of `set` and returns the consensus
+/// of those with frequency `>= threshold` (`> 0.5` for majority rule).
+pub fn consensus(set: &TreeSet, burnin: usize, threshold: f64) -> Result<Tree, String> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("threshold must be between 0 and 1, got {threshold}"));
+    }
+    if burnin >= set.len() {
+        return Err(format!("burn-in of {burnin} leaves none of the {} trees", set.len()));
+    }
+    let first = set.tree(burnin).map_err(|e| format!("tree {}: {e}", burnin + 1))?;
+    let taxa = Taxa::from_tree(&first, burnin)?;
+    let mut clades: HashMap<Clade, CladeStats> = HashMap::new();
+    for i in burnin..set.len() {
+        let tree = set.tree(i).map_err(|e| format!("tree {}: {e}", i + 1))?;
+        count_clades(&tree, i, &taxa, &mut clades)?;
+    }
+
+    let n_trees = set.len() - burnin;
+    Ok(build_tree(&taxa.names, clades, n_trees, threshold))
+}
+
+/// Adds the clades of one tree (including tips and the root) to `clades`.
+fn count_clades(tree: &Tree, i: usize, taxa: &Taxa, clades: &mut HashMap<Clade, CladeStats>) -> Result<(), String> {
+    for (n, clade) in taxa.clades(tree, i)?.into_iter().enumerate() {
+        let stats = clades.entry(clade).or_default();
+        if stats.last_tree != Some(i) {
+            stats.count += 1;
+            stats.last_tree = Some(i);
+        }
+        if let Some(len) = tree.nodes[n].length {
+            stats.length_sum += len;
+            stats.length_count += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Assembles accepted clades into a tree, written out in pre-order.
+fn build_tree(taxa: &[String], clades: HashMap<Clade, CladeStats>, n_trees: usize, threshold: f64) -> Tree {
+    let freq = |s: &CladeStats| s.count as f64 / n_trees as f64;
+    // Majority rule takes clades in more than half the trees, not exactly half.
+    let passes = |s: &CladeStats| if threshold == 0.5 { s.count * 2 > n_trees } else { freq(s) >= threshold };
+    let mut tip_stats: Vec<Option<CladeStats>> = taxa.iter().map(|_| None).collect();
+    let mut candidates: Vec<(Clade, CladeStats)> = Vec::new();
+    for (clade, stats) in clades {
+        if clades::size(&clade) == 1 {
+            tip_stats[clades::first_taxon(&clade)] = Some(stats);
+        } else if passes(&stats) {
+            candidates.push((clade, stats));
+        }
+    }
+    candidates.sort_by(|(a, sa), (b, sb)| sb.count.cmp(&sa.count).then(clades::size(b).cmp(&clades::size(a))).then(a.cmp(b)));
+
+    let mut accepted: Vec<(Clade, CladeStats)> = Vec::new();
+    for (clade, stats) in candidates {
+        if accepted.iter().all(|(c, _)| clades::is_subset(&clade, c) || clades::is_subset(c, &clade) || clades::is_disjoint(c, &clade)) {
+            accepted.push((clade, stats));
+        }
+    }
+    // Largest first, so every clade's parent has already been placed.
+    accepted.sort_by_key(|(c, _)| std::cmp::Reverse(clades::size(c)));
+
+    // Nested children lists over accepted clades, then tips; ids >= n are tips.
+    let n = accepted.len();
+    let mut owner = vec![usize::MAX; taxa.len()];
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (id, (clade, _)) in accepted.iter().enumerate() {
+        let first = clades::first_taxon(clade);
+        if owner[first] != usize::MAX {
+            children[owner[first]].push(id);
+        }
+        for (k, o) in owner.iter_mut().enumerate() {
+            if clades::contains(clade, k) {
+                *o = id;
+            }
+        }
+    }
+    for (k, &o) in owner.iter().enumerate() {
+        children[o].push(n + k);
+    }
+    let min_taxon = |id: usize| if id >= n { id - n } else { clades::first_taxon(&accepted[id].0) };
+    for list in &mut children {
+        list.sort_by_key(|&id| min_taxon(id));
+    }
+
+    let mut tree = Tree { rooted: Some(true), ..Default::default() };
+    tree.name = Some(if threshold == 0.5 {
+        "Majority-rule consensus".to_string()
+    } else {
+        format!("{}% consensus", (threshold * 100.0).round())
+    });
+    let mut stack = vec![(0usize, None)];
+    while let Some((id, parent)) = stack.pop() {
+        let row = tree.push_node(parent);
+        let node = &mut tree.nodes[row];
+        if id >= n {
+            node.label = Some(taxa[id - n].clone());
+            node.length = tip_stats[id - n].as_ref().and_then(mean_length);
+            continue;
+        }
+        let stats = &accepted[id].1;
+        if parent.is_some() {
+            node.length = mean_length(stats);
+        }
+        node.annotations.insert("clade_frequency".into(), Value::from(freq(stats)));
+        for &child in children[id].iter().rev() {
+            stack.push((child, Some(row)));
+        }
+    }
+    tree
+}
+
+fn mean_length(s: &CladeStats) -> Option<f64> {
+    (s.length_count > 0).then(|| s.length_sum / s.length_count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn set(text: &str) -> TreeSet {
+        TreeSet::open(text.to_string()).unwrap()
+    }
+
+    /// The consensus as Newick-like nesting of tip names.
+    fn shape(tree: &Tree) -> String {
+        fn walk(tree: &Tree, n: usize, out: &mut String) {
+            let children: Vec<usize> = (0..tree.nodes.len()).filter(|&c| tree.nodes[c].parent == Some(n)).collect();
+            if children.is_empty() {
+                out.push_str(tree.nodes[n].label.as_deref().unwrap_or_default());
+                return;
+            }
+            out.push('(');
+            for (i, &c) in children.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                walk(tree, c, out);
+            }
+            out.push(')');
+        }
+        let mut out = String::new();
+        walk(tree, 0, &mut out);
+        out
+    }
+
+    const TREES: &str = "(((a:1,b:1):1,c:2):1,d:3);\n(((a:3,b:3):1,d:2):1,c:3);\n(((a:1,c:1):1,b:2):1,d:3);\n";
+
+    #[test]
+    fn builds_the_majority_rule_tree() {
+        let tree = consensus(&set(TREES), 0, 0.5).unwrap();
+        assert_eq!(tree.name.as_deref(), Some("Majority-rule consensus"));
+        assert_eq!(shape(&tree), "(((a,b),c),d)");
+        let ab = tree.nodes.iter().position(|n| n.parent == Some(1) && n.label.is_none()).unwrap();
+        assert_eq!(tree.nodes[ab].annotations["clade_frequency"], json!(2.0 / 3.0));
+        // The mean length of the (a,b) branch over the two trees holding it.
+        assert_eq!(tree.nodes[ab].length, Some(1.0));
+    }
+
+    #[test]
+    fn leaves_out_clades_in_exactly_half_the_trees() {
+        let tree = consensus(&set("((a,b),(c,d));\n((a,c),(b,d));\n"), 0, 0.5).unwrap();
+        assert_eq!(shape(&tree), "(a,b,c,d)");
+    }
+
+    #[test]
+    fn applies_burnin_and_threshold() {
+        let tree = consensus(&set(TREES), 0, 1.0).unwrap();
+        assert_eq!(tree.name.as_deref(), Some("100% consensus"));
+        assert_eq!(shape(&tree), "(a,b,c,d)");
+        assert_eq!(shape(&consensus(&set(TREES), 2, 0.5).unwrap()), "(((a,c),b),d)");
+        assert!(consensus(&set(TREES), 3, 0.5).is_err());
+        assert!(consensus(&set(TREES), 0, 1.5).is_err());
+    }
+}
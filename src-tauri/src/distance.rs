@@ -0,0 +1,405 @@
+//! Distances between two trees on the taxa they share: Robinson–Foulds,
+//! the branch-score distance of Kuhner & Felsenstein (1994) and the quartet
+//! distance.
+//!
+//! Both trees are read as unrooted and restricted to the shared taxa, so the
+//! root and any tips found in only one tree do not count. Restriction needs
+//! no pruning: a branch's split is the set of shared tips below it, and a
+//! chain of branches left by a removed subtree all carry the same split, so
+//! their lengths simply add up.
+
+use crate::clades::{self, Clade};
+use crate::tree::Tree;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Largest number of shared taxa for which quartets are counted. The count
+/// visits every pair of nodes across the two trees, and more for polytomies.
+const QUARTET_LIMIT: usize = 2000;
+
+/// The distances between two trees and the taxa they do not share.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Comparison {
+    pub names: [String; 2],
+    pub shared_taxa: usize,
+    pub only_in_first: Vec<String>,
+    pub only_in_second: Vec<String>,
+    /// Non-trivial splits found in one tree but not the other.
+    pub robinson_foulds: usize,
+    /// Robinson–Foulds over the number of non-trivial splits in both trees.
+    pub normalised_robinson_foulds: f64,
+    /// Square root of the summed squared differences in the length of each
+    /// split, terminal branches included; absent unless both trees have
+    /// branch lengths.
+    pub branch_score: Option<f64>,
+    /// Quartets of shared taxa with a different topology in each tree (an
+    /// unresolved quartet differs from any resolved one); absent above
+    /// `QUARTET_LIMIT` shared taxa.
+    pub quartet_distance: Option<u64>,
+    pub quartets: Option<u64>,
+}
+
+/// Compares `first` and `second`, named `names` in the result.
+pub fn compare(first: &Tree, second: &Tree, names: [String; 2]) -> Result<Comparison, String> {
+    let labels = [tip_labels(first, &names[0])?, tip_labels(second, &names[1])?];
+    let in_second: HashSet<&str> = labels[1].iter().map(|(_, l)| *l).collect();
+    let shared: Vec<&str> = labels[0].iter().map(|(_, l)| *l).filter(|l| in_second.contains(l)).collect();
+    if shared.len() < 4 {
+        return Err(format!("The trees share {} taxa; at least 4 are needed to compare them", shared.len()));
+    }
+    let index: HashMap<&str, usize> = shared.iter().enumerate().map(|(i, &l)| (l, i)).collect();
+    let only = |side: usize| -> Vec<String> {
+        labels[side].iter().filter(|(_, l)| !index.contains_key(l)).map(|(_, l)| l.to_string()).collect()
+    };
+    let n = shared.len();
+    let clades = [shared_clades(first, &labels[0], &index), shared_clades(second, &labels[1], &index)];
+    let splits = [splits(first, &clades[0], n), splits(second, &clades[1], n)];
+
+    let non_trivial = |s: &Clade| (2..=n - 2).contains(&(clades::size(s) as usize));
+    let mut robinson_foulds = 0;
+    let mut total = 0;
+    for (side, other) in [(0, 1), (1, 0)] {
+        for split in splits[side].keys().filter(|s| non_trivial(s)) {
+            total += 1;
+            if !splits[other].contains_key(split) {
+                robinson_foulds += 1;
+            }
+        }
+    }
+    let normalised_robinson_foulds = if total == 0 { 0.0 } else { robinson_foulds as f64 / total as f64 };
+
+    let has_lengths = |tree: &Tree| tree.nodes.iter().any(|node| node.length.is_some());
+    let branch_score = (has_lengths(first) && has_lengths(second)).then(|| {
+        let mut sum = 0.0;
+        for (split, &a) in &splits[0] {
+            let b = splits[1].get(split).copied().unwrap_or(0.0);
+            sum += (a - b) * (a - b);
+        }
+        for (split, &b) in &splits[1] {
+            if !splits[0].contains_key(split) {
+                sum += b * b;
+            }
+        }
+        sum.sqrt()
+    });
+
+    let (quartet_distance, quartets) = if n <= QUARTET_LIMIT {
+        let (a, b) = (Nodes::new(first, &clades[0]), Nodes::new(second, &clades[1]));
+        (Some(quartet_distance(&a, &b, n)), Some(choose(n as u64, 4)))
+    } else {
+        (None, None)
+    };
+
+    Ok(Comparison {
+        shared_taxa: n,
+        only_in_first: only(0),
+        only_in_second: only(1),
+        robinson_foulds,
+        normalised_robinson_foulds,
+        branch_score,
+        quartet_distance,
+        quartets,
+        names,
+    })
+}
+
+/// The tips of `tree` with their labels, which must be unique.
+fn tip_labels<'a>(tree: &'a Tree, name: &str) -> Result<Vec<(usize, &'a str)>, String> {
+    let mut seen = HashSet::new();
+    let mut tips = Vec::new();
+    for t in tree.tips() {
+        let label = tree.nodes[t].label.as_deref().unwrap_or_default();
+        if !seen.insert(label) {
+            return Err(format!("{name} has more than one tip named '{label}'"));
+        }
+        tips.push((t, label));
+    }
+    Ok(tips)
+}
+
+/// The shared taxa below every node of `tree`, by row.
+fn shared_clades(tree: &Tree, tips: &[(usize, &str)], index: &HashMap<&str, usize>) -> Vec<Clade> {
+    let words = index.len().div_ceil(64);
+    let mut bits = vec![vec![0u64; words]; tree.nodes.len()];
+    for &(t, label) in tips {
+        if let Some(&k) = index.get(label) {
+            bits[t][k / 64] |= 1 << (k % 64);
+        }
+    }
+    // Parents precede children in the node table, as in `clades::Taxa::clades`.
+    for i in (1..tree.nodes.len()).rev() {
+        if let Some(p) = tree.nodes[i].parent {
+            let (head, tail) = bits.split_at_mut(i);
+            for (a, b) in head[p].iter_mut().zip(&tail[0]) {
+                *a |= b;
+            }
+        }
+    }
+    bits
+}
+
+/// Every split of `tree` on the shared taxa with its total branch length.
+/// A split is stored as the side without taxon 0; branches that separate no
+/// shared taxa are left out.
+fn splits(tree: &Tree, clades: &[Clade], n: usize) -> HashMap<Clade, f64> {
+    let mut out = HashMap::new();
+    for (i, node) in tree.nodes.iter().enumerate().skip(1) {
+        let size = clades::size(&clades[i]) as usize;
+        if size == 0 || size == n {
+            continue;
+        }
+        let split = if clades::contains(&clades[i], 0) { complement(&clades[i], n) } else { clades[i].clone() };
+        *out.entry(split).or_insert(0.0) += node.length.unwrap_or(0.0);
+    }
+    out
+}
+
+fn complement(c: &Clade, n: usize) -> Clade {
+    let mut out: Clade = c.iter().map(|w| !w).collect();
+    let tail = n % 64;
+    if tail != 0 {
+        *out.last_mut().unwrap() &= (1u64 << tail) - 1;
+    }
+    out
+}
+
+fn choose(n: u64, k: u64) -> u64 {
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+/// A tree as seen by the quartet count: the children of each node, the
+/// number of shared taxa below it and the shared taxon of each tip.
+struct Nodes<'a> {
+    clades: &'a [Clade],
+    children: Vec<Vec<usize>>,
+    size: Vec<u64>,
+    taxon: Vec<Option<usize>>,
+}
+
+impl<'a> Nodes<'a> {
+    fn new(tree: &Tree, clades: &'a [Clade]) -> Self {
+        let mut children = vec![Vec::new(); tree.nodes.len()];
+        for (i, node) in tree.nodes.iter().enumerate() {
+            if let Some(p) = node.parent {
+                children[p].push(i);
+            }
+        }
+        let size: Vec<u64> = clades.iter().map(|c| clades::size(c) as u64).collect();
+        let taxon = (0..size.len())
+            .map(|i| (children[i].is_empty() && size[i] == 1).then(|| clades::first_taxon(&clades[i])))
+            .collect();
+        Nodes { clades, children, size, taxon }
+    }
+}
+
+/// Quartets resolved differently by `a` and `b`, counted without listing
+/// them.
+///
+/// A quartet `ab|cd` has two centres in a tree: the node where `a` and `b`
+/// part, with `c` and `d` together in a third subtree, and the same for
+/// `cd`. Summed over every pair of nodes, one from each tree, the number of
+/// ways to draw such a pattern from the intersections of their subtrees
+/// counts each quartet resolved alike twice (S), and each quartet resolved
+/// in both trees but differently four times (D). With R₁ and R₂ the quartets
+/// resolved in each tree, the quartets that differ number R₁ + R₂ − 2S − D.
+fn quartet_distance(a: &Nodes, b: &Nodes, n: usize) -> u64 {
+    let total = n as u64;
+    let (mut alike2, mut unlike4) = (0i128, 0i128);
+    let mut below: Vec<Vec<u64>> = Vec::new();
+    let mut patterns = Patterns::default();
+    for u in 0..a.children.len() {
+        // Subtrees around u: its children and, away from the root, the rest.
+        let mut sizes: Vec<u64> = a.children[u].iter().map(|&c| a.size[c]).collect();
+        if u != 0 {
+            sizes.push(total - a.size[u]);
+        }
+        if sizes.iter().filter(|&&s| s > 0).count() < 3 {
+            continue;
+        }
+        // Shared taxa of each subtree of u below each node of b.
+        below.clear();
+        for &c in &a.children[u] {
+            below.push(overlap(&a.clades[c], b));
+        }
+        if u != 0 {
+            let mut outside = b.size.clone();
+            for counts in &below {
+                for (o, c) in outside.iter_mut().zip(counts) {
+                    *o -= c;
+                }
+            }
+            below.push(outside);
+        }
+        if patterns.m.len() < sizes.len() {
+            patterns.m.resize_with(sizes.len(), Vec::new);
+        }
+        for v in 0..b.children.len() {
+            let outside = total - b.size[v];
+            if b.children[v].len() + usize::from(v != 0 && outside > 0) < 3 {
+                continue;
+            }
+            for (row, (counts, &size)) in patterns.m.iter_mut().zip(below.iter().zip(&sizes)) {
+                row.clear();
+                row.extend(b.children[v].iter().map(|&c| i128::from(counts[c])));
+                if v != 0 {
+                    row.push(i128::from(size - counts[v]));
+                }
+            }
+            let (s, d) = patterns.count(sizes.len(), total);
+            alike2 += s;
+            unlike4 += d;
+        }
+    }
+    let resolved = |t: &Nodes| -> i128 {
+        let mut twice = 0;
+        for u in 0..t.children.len() {
+            let mut sizes: Vec<u64> = t.children[u].iter().map(|&c| t.size[c]).collect();
+            if u != 0 {
+                sizes.push(total - t.size[u]);
+            }
+            let squares: i128 = sizes.iter().map(|&s| i128::from(s).pow(2)).sum();
+            for k in sizes.into_iter().map(i128::from) {
+                let rest = i128::from(total) - k;
+                twice += k * (k - 1) / 2 * ((rest * rest - (squares - k * k)) / 2);
+            }
+        }
+        twice / 2
+    };
+    let (alike, unlike) = (alike2 / 2, unlike4 / 4);
+    (resolved(a) + resolved(b) - 2 * alike - unlike) as u64
+}
+
+/// Shared taxa of `clade` below each node of `b`.
+fn overlap(clade: &Clade, b: &Nodes) -> Vec<u64> {
+    let mut counts = vec![0u64; b.children.len()];
+    for v in (0..b.children.len()).rev() {
+        counts[v] = match b.taxon[v] {
+            Some(t) => u64::from(clades::contains(clade, t)),
+            None => b.children[v].iter().map(|&c| counts[c]).sum(),
+        };
+    }
+    counts
+}
+
+/// Pattern counts for one pair of nodes, with buffers kept between pairs.
+/// `m[i][j]` holds the taxa in subtree `i` of the first node and subtree `j`
+/// of the second. A pattern puts `c`, `d` in subtree `k` of the first and
+/// `k'` of the second; alike, `a` and `b` lie in distinct other subtrees of
+/// both; unlike, the second tree parts `a` from `c` instead, with `b` beside
+/// `d`.
+#[derive(Default)]
+struct Patterns {
+    m: Vec<Vec<i128>>,
+    row: Vec<i128>,
+    col: Vec<i128>,
+    /// Per row i: Σⱼ colⱼ·m[i][j] and Σⱼ m[i][j]²; per column likewise.
+    row_dot: Vec<i128>,
+    row_sq_of: Vec<i128>,
+    col_dot: Vec<i128>,
+    col_sq_of: Vec<i128>,
+    /// Row Gram matrix, flattened: Σⱼ m[i][j]·m[k][j] at `i * p + k`.
+    gram: Vec<i128>,
+}
+
+impl Patterns {
+    /// Counts (alike, unlike) patterns for the matrix filled into `self.m`
+    /// (its first `p` rows).
+    fn count(&mut self, p: usize, total: u64) -> (i128, i128) {
+        let m = &self.m[..p];
+        let q = m[0].len();
+        self.row.clear();
+        self.row.extend(m.iter().map(|r| r.iter().sum::<i128>()));
+        self.col.clear();
+        self.col.extend((0..q).map(|j| m.iter().map(|r| r[j]).sum::<i128>()));
+        let (row, col) = (&self.row, &self.col);
+        self.row_dot.clear();
+        self.row_dot.extend(m.iter().map(|r| r.iter().zip(col).map(|(x, c)| x * c).sum::<i128>()));
+        self.row_sq_of.clear();
+        self.row_sq_of.extend(m.iter().map(|r| r.iter().map(|x| x * x).sum::<i128>()));
+        self.col_dot.clear();
+        self.col_dot.extend((0..q).map(|j| m.iter().zip(row).map(|(r, s)| r[j] * s).sum::<i128>()));
+        self.col_sq_of.clear();
+        self.col_sq_of.extend((0..q).map(|j| m.iter().map(|r| r[j] * r[j]).sum::<i128>()));
+        self.gram.clear();
+        for a in m {
+            self.gram.extend(m.iter().map(|b| a.iter().zip(b).map(|(x, y)| x * y).sum::<i128>()));
+        }
+        let row_sq: i128 = row.iter().map(|r| r * r).sum();
+        let col_sq: i128 = col.iter().map(|c| c * c).sum();
+        let all_sq: i128 = self.row_sq_of.iter().sum();
+
+        let total = i128::from(total);
+        let (mut alike, mut unlike) = (0, 0);
+        for k in 0..p {
+            for kk in 0..q {
+                let x = m[k][kk];
+                if x == 0 {
+                    continue;
+                }
+                // Taxa outside row k and column k'.
+                let rest = total + x - row[k] - col[kk];
+                if x >= 2 {
+                    // Ordered (a, b) off row k and column k', in different
+                    // rows and columns: all, less same row, less same column,
+                    // plus same cell.
+                    let rows = row_sq - row[k] * row[k] - 2 * (self.col_dot[kk] - row[k] * x) + self.col_sq_of[kk] - x * x;
+                    let cols = col_sq - col[kk] * col[kk] - 2 * (self.row_dot[k] - col[kk] * x) + self.row_sq_of[k] - x * x;
+                    let cells = all_sq - self.row_sq_of[k] - self.col_sq_of[kk] + x * x;
+                    alike += x * (x - 1) / 2 * ((rest * rest - rows - cols + cells) / 2);
+                }
+                // a in (i, i') off row k and column k'; b in column k' off
+                // rows k and i; c in row k off columns k' and i'; d in (k, k').
+                let (bs, cs) = (col[kk] - x, row[k] - x);
+                let mut sum = bs * cs * rest;
+                sum -= bs * (self.row_dot[k] - self.row_sq_of[k] - x * (col[kk] - x));
+                sum -= cs * (self.col_dot[kk] - self.col_sq_of[kk] - x * (row[k] - x));
+                for (i, r) in m.iter().enumerate().filter(|&(i, _)| i != k) {
+                    sum += r[kk] * (self.gram[i * p + k] - r[kk] * x);
+                }
+                unlike += x * sum;
+            }
+        }
+        (alike, unlike)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::newick::Parser;
+
+    fn compare_texts(first: &str, second: &str) -> Result<Comparison, String> {
+        let tree = |text| Parser::new(text, 0).parse_tree().unwrap();
+        compare(&tree(first), &tree(second), ["first".into(), "second".into()])
+    }
+
+    #[test]
+    fn identical_trees_are_zero_apart() {
+        let text = "((a:1,b:2):1,(c:1,d:1):1,e:1);";
+        let c = compare_texts(text, "(e:1,(d:1,c:1):1,(b:2,a:1):1);").unwrap();
+        assert_eq!((c.robinson_foulds, c.quartet_distance, c.quartets), (0, Some(0), Some(5)));
+        assert_eq!(c.branch_score, Some(0.0));
+    }
+
+    #[test]
+    fn counts_known_differences() {
+        let c = compare_texts("((a:1,b:1):1,(c:1,d:1):1);", "((a:1,c:1):2,(b:1,d:1):1);").unwrap();
+        // Unrooted, each tree has one split, ab|cd against ac|bd.
+        assert_eq!(c.robinson_foulds, 2);
+        assert_eq!(c.normalised_robinson_foulds, 1.0);
+        assert_eq!((c.quartet_distance, c.quartets), (Some(1), Some(1)));
+        // The root's two branches form one split of length 2 in the first
+        // tree and 3 in the second; both splits are missing from the other.
+        assert_eq!(c.branch_score, Some(13f64.sqrt()));
+    }
+
+    #[test]
+    fn restricts_to_shared_taxa() {
+        let c = compare_texts("(((a,b),x),(c,d));", "((a,b),(c,(d,y)));").unwrap();
+        assert_eq!((c.shared_taxa, c.robinson_foulds), (4, 0));
+        assert_eq!((c.only_in_first.as_slice(), c.only_in_second.as_slice()), (&["x".to_string()][..], &["y".to_string()][..]));
+        assert_eq!(c.branch_score, None);
+        assert!(compare_texts("((a,b),c);", "((a,b),c);").is_err());
+    }
+}
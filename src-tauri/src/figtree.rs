@@ -0,0 +1,113 @@
+//! FigTree's NEXUS extras: `[&!color=...]` node colours and the
+//! `begin figtree; set ...; end;` block of display options.
+//!
+//! Colours become the `user_colour` annotation PearTree uses for colours
+//! picked by hand. FigTree wrote them as `#rrggbb`, or in older versions as
+//! a signed Java ARGB integer (`#-16776961`). The display options that have
+//! a PearTree counterpart are mapped onto the settings object `loadTree`
+//! applies from the file; the rest are ignored.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// FigTree's name for colouring by the colours set by hand.
+const USER_SELECTION: &str = "User selection";
+
+/// Replaces a FigTree `!color` annotation with `user_colour`.
+pub fn user_colour(annotations: &mut Map<String, Value>) {
+    let Some(raw) = annotations.remove("!color") else { return };
+    let raw = match &raw {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if let Some(colour) = colour(&raw) {
+        annotations.insert("user_colour".into(), Value::String(colour));
+    }
+}
+
+/// `#rrggbb` for a FigTree colour, whether hex or a Java ARGB integer.
+fn colour(raw: &str) -> Option<String> {
+    let body = raw.trim().trim_matches('"').strip_prefix('#')?;
+    if body.len() == 6 && body.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Some(format!("#{}", body.to_ascii_lowercase()));
+    }
+    let argb = body.parse::<i64>().ok()?;
+    Some(format!("#{:06x}", argb & 0xff_ffff))
+}
+
+/// Maps the options of a FigTree block onto PearTree settings. Attributes
+/// PearTree does not have are passed through anyway: `loadTree` only applies
+/// an annotation setting when the tree has that annotation.
+pub fn settings(options: &HashMap<String, String>) -> Map<String, Value> {
+    let get = |key: &str| options.get(key).map(|v| v.trim_matches('"'));
+    let shown = |key: &str| get(key).map(|v| v.eq_ignore_ascii_case("true"));
+    let attribute = |key: &str| {
+        get(key).filter(|v| !v.eq_ignore_ascii_case("null")).map(|v| {
+            if v == USER_SELECTION { "user_colour".to_string() } else { v.to_string() }
+        })
+    };
+    let mut out = Map::new();
+
+    match (shown("tipLabels.isShown"), get("tipLabels.displayAttribute")) {
+        (Some(false), _) => {
+            out.insert("tipLabelShow".into(), "off".into());
+        }
+        (_, Some("Names")) => {
+            out.insert("tipLabelShow".into(), "name".into());
+        }
+        (_, Some(field)) => {
+            out.insert("tipLabelShow".into(), field.into());
+        }
+        _ => {}
+    }
+    for (prefix, setting) in [("nodeLabels", "nodeLabelAnnotation"), ("branchLabels", "branchLabelAnnotation")] {
+        if let Some(on) = shown(&format!("{prefix}.isShown")) {
+            let field = attribute(&format!("{prefix}.displayAttribute")).filter(|_| on).unwrap_or_default();
+            out.insert(setting.into(), field.into());
+        }
+    }
+
+    if let Some(by) = attribute("appearance.branchColorAttribute") {
+        out.insert("tipColourBy".into(), by.clone().into());
+        out.insert("nodeColourBy".into(), by.into());
+    }
+    for (key, setting) in [
+        ("tipLabels.colorAttribute", "labelColourBy"),
+        ("nodeLabels.colorAttribute", "nodeLabelColourBy"),
+        ("branchLabels.colorAttribute", "branchLabelColourBy"),
+        ("nodeShape.colourAttribute", "nodeColourBy"),
+    ] {
+        if let Some(by) = attribute(key) {
+            out.insert(setting.into(), by.into());
+        }
+    }
+    if shown("legend.isShown") == Some(true) {
+        if let Some(field) = attribute("legend.attribute") {
+            out.insert("legendAnnotation".into(), field.into());
+        }
+    }
+
+    if let Some(on) = shown("nodeBars.isShown") {
+        out.insert("nodeBarsEnabled".into(), if on { "on" } else { "off" }.into());
+    }
+    for (key, setting) in [
+        ("nodeBars.barWidth", "nodeBarsWidth"),
+        ("appearance.branchLineWidth", "branchWidth"),
+        ("tipLabels.fontSize", "tipLabelFontSize"),
+        ("nodeLabels.fontSize", "nodeLabelFontSize"),
+    ] {
+        if let Some(n) = get(key).and_then(|v| v.parse::<f64>().ok()) {
+            out.insert(setting.into(), n.to_string().into());
+        }
+    }
+    for (key, setting) in [("appearance.backgroundColour", "canvasBgColor"), ("appearance.foregroundColour", "branchColor")] {
+        if let Some(c) = get(key).and_then(colour) {
+            out.insert(setting.into(), c.into());
+        }
+    }
+    if shown("trees.order") == Some(true) {
+        let order = if get("trees.orderType") == Some("decreasing") { "desc" } else { "asc" };
+        out.insert("nodeOrder".into(), order.into());
+    }
+    out
+}
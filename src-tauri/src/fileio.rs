@@ -0,0 +1,268 @@
+//! Reading tree and annotation files from disk.
+//!
+//! Compressed files (gzip/bgzip, xz, zstd, bzip2) are recognised by their
+//! magic bytes rather than their extension and decompressed on the fly, so
+//! `tree.nwk.gz` and a gzipped file saved as `tree.nwk` both just work.
+//!
+//! Text is not assumed to be UTF-8: a BOM wins, then a BOM-less UTF-16 check,
+//! then valid UTF-8, and finally a legacy-encoding guess (Windows-1252 and
+//! friends, as exported by Excel on Windows).
+//!
+//! Reads are streamed in chunks with a progress callback that can also cancel
+//! the read, so the Tauri layer can run loads as cancellable background jobs.
+
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Why a file could not be read, serialised to JS as
+/// `{ kind, message, path, encoding?, offset? }` so the frontend can keep
+/// showing `err.message` while also getting the details.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadError {
+    pub kind: ReadErrorKind,
+    pub message: String,
+    pub path: String,
+    /// Encoding the file was being decoded as (`decode` errors only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<&'static str>,
+    /// Byte offset of the first undecodable sequence (`decode` errors), or of
+    /// the syntax error in the decoded text (`parse` errors).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadErrorKind {
+    Io,
+    Decode,
+    Parse,
+    Cancelled,
+}
+
+impl ReadError {
+    pub fn io(path: &Path, err: impl fmt::Display) -> Self {
+        ReadError {
+            kind: ReadErrorKind::Io,
+            message: format!("Failed to read {}: {err}", path.display()),
+            path: path.display().to_string(),
+            encoding: None,
+            offset: None,
+        }
+    }
+
+    pub fn cancelled(path: &Path) -> Self {
+        ReadError {
+            kind: ReadErrorKind::Cancelled,
+            message: format!("Loading {} was cancelled", path.display()),
+            path: path.display().to_string(),
+            encoding: None,
+            offset: None,
+        }
+    }
+
+    pub fn parse(path: &Path, err: crate::newick::ParseError) -> Self {
+        ReadError {
+            kind: ReadErrorKind::Parse,
+            message: format!("Failed to parse {}: {err}", path.display()),
+            path: path.display().to_string(),
+            encoding: None,
+            offset: Some(err.offset),
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Decoded file content together with the encoding it was decoded from.
+pub struct TextFile {
+    pub text: String,
+    /// WHATWG name of the detected encoding, e.g. `"UTF-8"` or `"windows-1252"`.
+    pub encoding: &'static str,
+}
+
+/// Extensions of the compressed containers we can open, for dialog filters.
+pub const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "xz", "zst", "bz2"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+/// Identifies the compression format from the first bytes of a file.
+pub fn detect_compression(bytes: &[u8]) -> Compression {
+    match bytes {
+        [0x1f, 0x8b, ..] => Compression::Gzip,
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+        [b'B', b'Z', b'h', b'1'..=b'9', ..] => Compression::Bzip2,
+        _ => Compression::None,
+    }
+}
+
+/// Wraps `src` in the decoder for `compression`.
+fn decoder<'a>(compression: Compression, src: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(src),
+        // MultiGzDecoder so bgzip output (many concatenated members) reads in full.
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(src)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(src)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(src)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(src)),
+    })
+}
+
+/// Bytes read between progress callbacks.
+const PROGRESS_CHUNK: u64 = 1 << 20;
+
+/// Marker error returned through `io::Read` when a progress callback asks to stop.
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Counts the (compressed) bytes pulled from the file and reports them to
+/// `progress` every `PROGRESS_CHUNK` bytes and at end of file.
+struct ProgressReader<R, F> {
+    inner: R,
+    read: u64,
+    reported: u64,
+    total: u64,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(u64, u64) -> bool> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if (n == 0 && self.read > self.reported) || self.read - self.reported >= PROGRESS_CHUNK {
+            self.reported = self.read;
+            if !(self.progress)(self.read, self.total) {
+                return Err(io::Error::other(Cancelled));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Reads a file, decompressing it if needed, and returns the raw bytes.
+/// `progress(bytes_read, total_bytes)` is called as the file is read (counting
+/// on-disk, i.e. compressed, bytes); returning `false` aborts the read with a
+/// `cancelled` error.
+pub fn read_bytes(path: &Path, progress: impl FnMut(u64, u64) -> bool) -> Result<Vec<u8>, ReadError> {
+    let fail = |e: io::Error| {
+        if e.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+            ReadError::cancelled(path)
+        } else {
+            ReadError::io(path, e)
+        }
+    };
+    let file = File::open(path).map_err(fail)?;
+    let total = file.metadata().map_err(fail)?.len();
+    let mut reader = BufReader::with_capacity(PROGRESS_CHUNK as usize, file);
+    let compression = detect_compression(reader.fill_buf().map_err(fail)?);
+    let src = ProgressReader { inner: reader, read: 0, reported: 0, total, progress };
+    let mut bytes = Vec::with_capacity(if compression == Compression::None { total as usize } else { 0 });
+    decoder(compression, src).and_then(|mut d| d.read_to_end(&mut bytes)).map_err(fail)?;
+    Ok(bytes)
+}
+
+/// Reads a (possibly compressed) text file in whatever encoding it uses and
+/// returns it transcoded to UTF-8. See `read_bytes` for `progress`.
+pub fn read_text(path: &Path, progress: impl FnMut(u64, u64) -> bool) -> Result<TextFile, ReadError> {
+    decode_text(path, read_bytes(path, progress)?)
+}
+
+/// Transcodes the bytes read from `path` to UTF-8, for callers that first
+/// check whether a file is binary.
+pub fn decode_text(path: &Path, bytes: Vec<u8>) -> Result<TextFile, ReadError> {
+    let (encoding, bom_len) = sniff_encoding(&bytes);
+    match decode_strict(encoding, &bytes[bom_len..]) {
+        Ok(text) => Ok(TextFile { text, encoding: encoding.name() }),
+        Err(offset) => Err(ReadError {
+            kind: ReadErrorKind::Decode,
+            message: format!(
+                "Failed to read {}: invalid {} data at byte {}",
+                path.display(),
+                encoding.name(),
+                bom_len + offset,
+            ),
+            path: path.display().to_string(),
+            encoding: Some(encoding.name()),
+            offset: Some(bom_len + offset),
+        }),
+    }
+}
+
+/// Picks the encoding for `bytes`, returning it with the length of any BOM.
+fn sniff_encoding(bytes: &[u8]) -> (&'static Encoding, usize) {
+    if let Some(found) = Encoding::for_bom(bytes) {
+        return found;
+    }
+    // Checked before UTF-8: BOM-less UTF-16 ASCII is also valid UTF-8 (NULs and all).
+    if let Some(utf16) = sniff_bomless_utf16(bytes) {
+        return (utf16, 0);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, 0);
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    (detector.guess(None, true), 0)
+}
+
+/// Mostly-ASCII text saved as UTF-16 without a BOM has a NUL in every other
+/// byte; which half holds the NULs tells us the byte order.
+fn sniff_bomless_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    let pairs = sample.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let even_nuls = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_nuls = sample.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    if odd_nuls * 10 >= pairs * 8 && even_nuls * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_nuls * 10 >= pairs * 8 && odd_nuls * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Decodes `bytes` without replacement characters, returning the byte offset
+/// of the first malformed sequence on failure.
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Result<String, usize> {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(bytes.len());
+    let mut pos = 0;
+    loop {
+        let (result, read) =
+            decoder.decode_to_string_without_replacement(&bytes[pos..], &mut text, true);
+        pos += read;
+        match result {
+            DecoderResult::InputEmpty => return Ok(text),
+            DecoderResult::OutputFull => text.reserve(bytes.len() - pos + 16),
+            DecoderResult::Malformed(bad, extra) => return Err(pos - bad as usize - extra as usize),
+        }
+    }
+}
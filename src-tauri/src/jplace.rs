@@ -0,0 +1,184 @@
+//! jplace (phylogenetic placement) reader, for EPA-ng and pplacer output.
+//!
+//! The reference tree numbers its edges with `{n}` (jplace v3) or `[n]`
+//! (older versions) after each branch length; these become an `edge_num`
+//! annotation on the node below the edge. Placements are not grafted onto
+//! the tree. Instead each edge gets an overlay of the queries placed on it:
+//! `placements` (query names), `placements_lwr` (like-weight ratios) and
+//! `placements_pendant_length` and `placements_distal_length`, one entry per
+//! placement, plus `placement_count`. A query counts once, with its
+//! multiplicity, on its best edge (highest like-weight ratio, else the first
+//! listed), so the counts over the tree sum to the number of queries.
+
+use crate::auspice::parse_json;
+use crate::newick::{self, ParseError};
+use crate::tree::Tree;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Returns true when `text` looks like a jplace document: a JSON object
+/// with `tree`, `placements` and `fields` members.
+pub fn is_jplace(text: &str) -> bool {
+    text.trim_start_matches('\u{feff}').trim_start().starts_with('{')
+        && text.contains("\"placements\"")
+        && text.contains("\"fields\"")
+        && text.contains("\"tree\"")
+}
+
+/// Reads the reference tree with the placements overlaid on its edges.
+pub fn parse_jplace(text: &str) -> Result<Tree, ParseError> {
+    let doc = parse_json(text)?;
+    let tree_text = doc
+        .get("tree")
+        .and_then(Value::as_str)
+        .ok_or_else(|| ParseError::new(0, "jplace has no \"tree\" string"))?;
+    let mut tree = edge_numbers_as_annotations(tree_text)
+        .and_then(|text| newick::Parser::new(&text, 0).parse_tree())
+        .map_err(|e| ParseError::new(0, format!("in jplace tree: {e}")))?;
+    let edges: HashMap<u64, usize> = tree
+        .nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(n, node)| Some((node.annotations.get("edge_num")?.as_u64()?, n)))
+        .collect();
+
+    let fields: Vec<&str> =
+        doc.get("fields").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str).collect();
+    let column = |name: &str| fields.iter().position(|&f| f == name);
+    let edge_col = column("edge_num").ok_or_else(|| ParseError::new(0, "jplace \"fields\" has no edge_num"))?;
+    let (lwr_col, pendant_col, distal_col) =
+        (column("like_weight_ratio"), column("pendant_length"), column("distal_length"));
+
+    let mut overlay: HashMap<usize, Map<String, Value>> = HashMap::new();
+    for placement in doc.get("placements").and_then(Value::as_array).into_iter().flatten() {
+        let queries = query_names(placement);
+        let multiplicity: f64 = queries.iter().map(|(_, m)| m).sum();
+        let rows: Vec<&[Value]> = placement
+            .get("p")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|row| row.as_array().map(Vec::as_slice).unwrap_or_default())
+            .collect();
+        let lwr = |row: &[Value]| lwr_col.and_then(|c| row.get(c)).and_then(Value::as_f64).unwrap_or(0.0);
+        // max_by keeps the last of equal ratios, so scan backwards for the first.
+        let best = (0..rows.len()).rev().max_by(|&a, &b| lwr(rows[a]).total_cmp(&lwr(rows[b])));
+        for (r, &row) in rows.iter().enumerate() {
+            let Some(edge) = row.get(edge_col).and_then(Value::as_u64) else { continue };
+            let &node = edges
+                .get(&edge)
+                .ok_or_else(|| ParseError::new(0, format!("placement on edge {edge}, which the tree does not have")))?;
+            let cell = |col: Option<usize>| col.and_then(|c| row.get(c)).cloned().unwrap_or(Value::Null);
+            let annots = overlay.entry(node).or_default();
+            for (name, _) in &queries {
+                push(annots, "placements", Value::from(name.as_str()));
+                push(annots, "placements_lwr", cell(lwr_col));
+                push(annots, "placements_pendant_length", cell(pendant_col));
+                push(annots, "placements_distal_length", cell(distal_col));
+            }
+            if best == Some(r) {
+                let count = annots.get("placement_count").and_then(Value::as_f64).unwrap_or(0.0) + multiplicity;
+                annots.insert("placement_count".into(), Value::from(count));
+            }
+        }
+    }
+    for (node, annots) in overlay {
+        tree.nodes[node].annotations.extend(annots);
+    }
+    Ok(tree)
+}
+
+/// The queries of one placement with their multiplicities, from `n` (names)
+/// or `nm` (`[name, multiplicity]` pairs).
+fn query_names(placement: &Value) -> Vec<(String, f64)> {
+    let names = placement.get("n").and_then(Value::as_array).into_iter().flatten();
+    let named = names.filter_map(|n| Some((n.as_str()?.to_string(), 1.0)));
+    let pairs = placement.get("nm").and_then(Value::as_array).into_iter().flatten();
+    let with_counts = pairs.filter_map(|p| Some((p.get(0)?.as_str()?.to_string(), p.get(1)?.as_f64().unwrap_or(1.0))));
+    named.chain(with_counts).collect()
+}
+
+/// Appends `value` to the array annotation `key`.
+fn push(annots: &mut Map<String, Value>, key: &str, value: Value) {
+    if let Value::Array(list) = annots.entry(key).or_insert_with(|| Value::Array(Vec::new())) {
+        list.push(value);
+    }
+}
+
+/// Rewrites `{n}` and `[n]` edge numbers as `[&edge_num=n]` comments the
+/// Newick parser reads as annotations. Quoted labels are copied untouched;
+/// an unterminated quote or bracket is an error.
+fn edge_numbers_as_annotations(tree: &str) -> Result<String, ParseError> {
+    let mut out = String::with_capacity(tree.len() + tree.len() / 4);
+    let mut rest = tree;
+    while let Some(i) = rest.find(['{', '[', '\'', '"']) {
+        out.push_str(&rest[..i]);
+        let open = rest.as_bytes()[i];
+        rest = &rest[i..];
+        let close = match open {
+            b'{' => '}',
+            b'[' => ']',
+            quote => quote as char,
+        };
+        let inner_end = rest[1..].find(close).ok_or_else(|| {
+            ParseError::new(tree.len() - rest.len(), format!("unterminated {}", open as char))
+        })? + 1;
+        let (inner, end) = (&rest[1..inner_end], inner_end + 1);
+        if matches!(open, b'{' | b'[') && !inner.is_empty() && inner.bytes().all(|b| b.is_ascii_digit()) {
+            out.push_str(&format!("[&edge_num={inner}]"));
+        } else {
+            out.push_str(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn jplace(tree: &str, placements: &str) -> String {
+        format!(
+            r#"{{"version":3,"tree":"{tree}","placements":{placements},"fields":["edge_num","like_weight_ratio","pendant_length","distal_length"]}}"#
+        )
+    }
+
+    #[test]
+    fn overlays_placements_on_edges() {
+        let text = jplace(
+            "((a:1{0},'b{x}':1{1}):1{2},c:2{3}){4};",
+            r#"[{"p":[[1,0.3,0.1,0.2],[3,0.7,0.05,1.0]],"nm":[["q1",2]]},{"p":[[1,1.0,0.2,0.5]],"n":["q2"]}]"#,
+        );
+        assert!(is_jplace(&text));
+        let tree = parse_jplace(&text).unwrap();
+        let labels: Vec<_> = tree.nodes.iter().map(|n| n.label.as_deref()).collect();
+        assert_eq!(labels, [None, None, Some("a"), Some("b{x}"), Some("c")]);
+        let b = &tree.nodes[3].annotations;
+        assert_eq!(b["edge_num"], json!(1));
+        assert_eq!(b["placements"], json!(["q1", "q2"]));
+        assert_eq!(b["placements_lwr"], json!([0.3, 1.0]));
+        assert_eq!(b["placements_distal_length"], json!([0.2, 0.5]));
+        // q1 counts on its best edge only; q2 on its single edge.
+        assert_eq!(b["placement_count"], json!(1.0));
+        assert_eq!(tree.nodes[4].annotations["placement_count"], json!(2.0));
+    }
+
+    #[test]
+    fn reads_old_style_edge_numbers() {
+        let tree = parse_jplace(&jplace("(a:1[0],b:1[1])[2];", "[]")).unwrap();
+        assert_eq!(tree.nodes[2].annotations["edge_num"], json!(1));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for tree in ["(a,b)'é", "(a,b)[é", "(a:1{0},b:1[12);", "(a:1{0},b:1{1});"] {
+            let placements = r#"[{"p":[[5,1.0,0,0]],"n":["q"]}]"#;
+            assert!(parse_jplace(&jplace(tree, placements)).is_err(), "{tree:?} parsed");
+        }
+        assert!(parse_jplace(r#"{"placements":[],"fields":["edge_num"]}"#).is_err());
+        assert!(parse_jplace(r#"{"tree":"(a{0},b{1});","placements":[],"fields":[]}"#).is_err());
+    }
+}
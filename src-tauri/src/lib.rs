@@ -7,10 +7,36 @@ use tauri_plugin_dialog::DialogExt;
 use base64::engine::{Engine as _, general_purpose::STANDARD as BASE64};
 use std::collections::HashMap;
 use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Mutex,
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc, Mutex,
 };
 
+mod auspice;
+mod clades;
+mod consensus;
+mod dating;
+mod distance;
+mod figtree;
+mod fileio;
+mod jplace;
+mod mcc;
+mod newick;
+mod nexml;
+mod nexus;
+mod phyloxml;
+mod rooting;
+mod rtt;
+mod support;
+mod taxonium;
+mod tree;
+mod treeset;
+mod usher;
+mod writer;
+
+/// Uncompressed tree file extensions, shared by the open dialog filter and
+/// kept in step with `fileAssociations` in tauri.conf.json.
+const TREE_EXTENSIONS: &[&str] = &["tree", "tre", "nex", "nexus", "treefile", "contree", "support", "nwk", "newick", "nhx", "phyloxml", "nexml", "xml", "json", "jsonl", "jplace", "pb"];
+
 /// Managed state: maps command-id strings to their live MenuItem handles.
 /// window.set_menu() is unsupported on macOS; there is one global app menu,
 /// so we track handles here for set_menu_item_enabled / set_menu_item_text.
@@ -23,6 +49,10 @@ struct WindowCounter(AtomicU32);
 /// set by `new_window`, consumed once by `take_pending_file` on startup.
 struct PendingFiles(Mutex<HashMap<String, String>>);
 
+/// Trees computed in Rust (e.g. a consensus) waiting to be shown in a newly
+/// created window: window_label → tree. Consumed once by `take_pending_tree`.
+struct PendingTrees(Mutex<HashMap<String, tree::TreeFile>>);
+
 /// The pending update returned by check_for_updates, held until install_update consumes it.
 struct PendingUpdate(Mutex<Option<tauri_plugin_updater::Update>>);
 
@@ -31,6 +61,108 @@ struct PendingUpdate(Mutex<Option<tauri_plugin_updater::Update>>);
 /// native OS window activation (reliable on macOS, unlike JS onFocusChanged).
 struct LastFocusedWindow(Mutex<String>);
 
+/// Cancel flags for in-flight file loads and other background jobs, keyed by
+/// window label and job kind. Each window runs at most one job of each kind
+/// at a time; `cancel_load` and `cancel_date_test` set their flags.
+struct LoadJobs(Mutex<HashMap<(String, JobKind), Arc<AtomicBool>>>);
+
+/// What a background job is doing. Jobs of different kinds in the same
+/// window neither supersede nor cancel each other.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum JobKind {
+    /// File loads and the tree builds that read a window's trees.
+    Load,
+    /// The RTT panel's date-randomisation test.
+    DateTest,
+}
+
+/// Multi-tree files open in each window, keyed by window label, so
+/// `get_tree` can fetch any tree without re-reading the file. Single-tree
+/// files are not kept.
+struct TreeSets(Mutex<HashMap<String, Arc<treeset::TreeSet>>>);
+
+/// The tree each window is showing, as last read or built in Rust, keyed by
+/// window label, so `compare_trees` can reach another window's tree. Edits
+/// made in the window (rerooting, hiding) are not reflected; the distances
+/// do not depend on them. A tree loaded without the native parser (the JS
+/// fallback, a pasted tree) removes the window's entry via
+/// `forget_window_tree`.
+struct WindowTrees(Mutex<HashMap<String, Arc<tree::Tree>>>);
+
+/// Payload of the `load-progress-{label}` event.
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoadProgress {
+    bytes_read: u64,
+    total_bytes: u64,
+}
+
+/// Payload of the `date-test-progress-{label}` event: permutations done.
+#[derive(Clone, serde::Serialize)]
+struct JobProgress {
+    done: usize,
+    total: usize,
+}
+
+/// A file load or other job running on behalf of one window. Registers a
+/// cancel flag in LoadJobs under its kind when started and removes it again when dropped.
+struct LoadJob {
+    app: tauri::AppHandle,
+    label: String,
+    kind: JobKind,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl LoadJob {
+    fn start(app: &tauri::AppHandle, window: &tauri::WebviewWindow) -> Self {
+        Self::start_kind(app, window, JobKind::Load)
+    }
+
+    fn start_kind(app: &tauri::AppHandle, window: &tauri::WebviewWindow, kind: JobKind) -> Self {
+        let label = window.label().to_string();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        // A new job supersedes any job of the same kind still running in the
+        // same window.
+        let previous = app.state::<LoadJobs>().0.lock().unwrap().insert((label.clone(), kind), cancelled.clone());
+        if let Some(previous) = previous {
+            previous.store(true, Ordering::SeqCst);
+        }
+        LoadJob { app: app.clone(), label, kind, cancelled }
+    }
+
+    /// Progress callback for `fileio::read_text`: emits `load-progress-{label}`
+    /// to the owning window (same routing as menu events) and returns false
+    /// once the load has been cancelled.
+    fn progress(&self, bytes_read: u64, total_bytes: u64) -> bool {
+        self.emit("load-progress", LoadProgress { bytes_read, total_bytes });
+        !self.is_cancelled()
+    }
+
+    /// Emits `{event}-{label}` to the owning window only.
+    fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) {
+        self.app.emit_to(
+            EventTarget::WebviewWindow { label: self.label.clone() },
+            &format!("{event}-{}", self.label),
+            payload,
+        ).ok();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for LoadJob {
+    fn drop(&mut self) {
+        let jobs = self.app.state::<LoadJobs>();
+        let mut jobs = jobs.0.lock().unwrap();
+        let key = (self.label.clone(), self.kind);
+        if jobs.get(&key).is_some_and(|flag| Arc::ptr_eq(flag, &self.cancelled)) {
+            jobs.remove(&key);
+        }
+    }
+}
+
 /// Build the application menu and return a (Menu, item-map) pair.
 /// On macOS the menu is app-wide (window.set_menu is unsupported),
 /// so this is called once at startup.
@@ -57,6 +189,7 @@ fn build_app_menu(
 
     let new_win      = MenuItem::with_id(manager, "new-window",   "New Window",                  true, Some("CmdOrCtrl+N"))?;
     let open_file    = MenuItem::with_id(manager, "open-file",    "Open Tree\u{2026}",                true, Some("CmdOrCtrl+O"))?;
+    let open_usher   = MenuItem::with_id(manager, "open-usher-subtree", "Open UShER Subtree\u{2026}", true, None::<&str>)?;
     let import_annot = MenuItem::with_id(manager, "import-annot", "Import Annotations\u{2026}",  true, Some("CmdOrCtrl+Shift+A"))?;
     let export_tree  = MenuItem::with_id(manager, "export-tree",  "Export Tree\u{2026}",          true, Some("CmdOrCtrl+E"))?;
     let export_image = MenuItem::with_id(manager, "export-image", "Export Image\u{2026}",         true, Some("CmdOrCtrl+Shift+E"))?;
@@ -68,6 +201,7 @@ fn build_app_menu(
         &new_win,
         &PredefinedMenuItem::separator(manager)?,
         &open_file,
+        &open_usher,
         &import_annot,
         &curate_annot,
         &manage_filters,
@@ -146,6 +280,7 @@ fn build_app_menu(
     let tree_midpoint             = MenuItem::with_id(manager, "tree-midpoint",             "Midpoint Root",           true,  Some("CmdOrCtrl+M"))?;
     let tree_temporal_root_global = MenuItem::with_id(manager, "tree-temporal-root-global", "Global Temporal Root",    true,  Some("CmdOrCtrl+T"))?;
     let tree_temporal_root        = MenuItem::with_id(manager, "tree-temporal-root",        "Optimise Root on Branch", true,  Some("CmdOrCtrl+Shift+T"))?;
+    let tree_time_tree            = MenuItem::with_id(manager, "tree-time-tree",            "Estimate Time Tree\u{2026}", false, None::<&str>)?;
     let tree_hide                 = MenuItem::with_id(manager, "tree-hide",                 "Hide Nodes",              true,  Some("CmdOrCtrl+Backspace"))?;
     let tree_show                 = MenuItem::with_id(manager, "tree-show",                 "Show Nodes",              true,  Some("CmdOrCtrl+Shift+Backspace"))?;
     let tree_collapse_clade       = MenuItem::with_id(manager, "tree-collapse-clade",       "Collapse Clade",          true,  Some("CmdOrCtrl+1"))?;
@@ -154,6 +289,12 @@ fn build_app_menu(
     let tree_clear_colours        = MenuItem::with_id(manager, "tree-clear-colours",        "Clear Colours",           true,  Some("CmdOrCtrl+Shift+K"))?;
     let tree_highlight_clade      = MenuItem::with_id(manager, "tree-highlight-clade",      "Highlight Clade",         false, Some("CmdOrCtrl+Shift+L"))?;
     let tree_clear_highlights     = MenuItem::with_id(manager, "tree-clear-highlights",     "Remove Highlight",        false, None::<&str>)?;
+    let tree_prev_tree            = MenuItem::with_id(manager, "tree-prev-tree",            "Previous Tree",           false, Some("CmdOrCtrl+Alt+["))?;
+    let tree_next_tree            = MenuItem::with_id(manager, "tree-next-tree",            "Next Tree",               false, Some("CmdOrCtrl+Alt+]"))?;
+    let tree_goto_tree            = MenuItem::with_id(manager, "tree-goto-tree",            "Go to Tree\u{2026}",       false, None::<&str>)?;
+    let tree_consensus            = MenuItem::with_id(manager, "tree-consensus",            "Consensus Tree\u{2026}",   false, None::<&str>)?;
+    let tree_mcc                  = MenuItem::with_id(manager, "tree-mcc",                  "MCC Tree\u{2026}",         false, None::<&str>)?;
+    let tree_compare              = MenuItem::with_id(manager, "tree-compare",              "Compare Trees\u{2026}",    false, None::<&str>)?;
 
     let tree_menu = Submenu::with_items(manager, "Tree", true, &[
         &tree_order_up,
@@ -166,6 +307,7 @@ fn build_app_menu(
         &tree_midpoint,
         &tree_temporal_root_global,
         &tree_temporal_root,
+        &tree_time_tree,
         &PredefinedMenuItem::separator(manager)?,
         &tree_hide,
         &tree_show,
@@ -178,6 +320,14 @@ fn build_app_menu(
         &PredefinedMenuItem::separator(manager)?,
         &tree_paint,
         &tree_clear_colours,
+        &PredefinedMenuItem::separator(manager)?,
+        &tree_prev_tree,
+        &tree_next_tree,
+        &tree_goto_tree,
+        &tree_consensus,
+        &tree_mcc,
+        &PredefinedMenuItem::separator(manager)?,
+        &tree_compare,
     ])?;
 
     let window_menu = Submenu::with_items(manager, "Window", true, &[
@@ -237,6 +387,7 @@ fn build_app_menu(
     for (id, item) in [
         ("new-window",       new_win),
         ("open-file",        open_file),
+        ("open-usher-subtree", open_usher),
         ("import-annot",     import_annot),
         ("export-tree",      export_tree),
         ("export-image",     export_image),
@@ -267,6 +418,7 @@ fn build_app_menu(
         ("tree-midpoint",             tree_midpoint),
         ("tree-temporal-root-global", tree_temporal_root_global),
         ("tree-temporal-root",        tree_temporal_root),
+        ("tree-time-tree",            tree_time_tree),
         ("tree-hide",        tree_hide),
         ("tree-show",        tree_show),
         ("tree-collapse-clade", tree_collapse_clade),
@@ -279,6 +431,12 @@ fn build_app_menu(
         ("tree-clear-highlights", tree_clear_highlights),
         ("tree-paint",       tree_paint),
         ("tree-clear-colours", tree_clear_colours),
+        ("tree-prev-tree",     tree_prev_tree),
+        ("tree-next-tree",     tree_next_tree),
+        ("tree-goto-tree",     tree_goto_tree),
+        ("tree-consensus",     tree_consensus),
+        ("tree-mcc",           tree_mcc),
+        ("tree-compare",       tree_compare),
         ("show-help",           show_help),
         ("open-manual",         open_manual),
         ("check-for-updates",   check_updates),
@@ -315,18 +473,25 @@ fn set_menu_item_text(
     item.set_text(text).map_err(|e| e.to_string())
 }
 
-/// Opens a native OS file picker filtered to tree file types, reads the
-/// selected file, and returns `{"name": "...", "content": "...", "path": "..."}` to JS.
-/// Returns `null` if the user cancels.
+/// Opens a native OS file picker filtered to tree file types and returns
+/// `{"name": "...", "path": "..."}` to JS, which loads the file through
+/// `parse_tree`. The file is not read here, so a large tree is read once and
+/// its text never crosses IPC. Returns `null` if the user cancels.
 ///
 /// Must be `async` so Tauri runs it on a worker thread instead of the main
 /// thread — blocking_pick_file() blocks its caller, and calling it on the
 /// main thread freezes the WebKit event loop (spinning wheel).
 #[tauri::command]
 async fn pick_tree_file(app: tauri::AppHandle) -> Result<Option<serde_json::Value>, String> {
+    let tree_exts: Vec<&str> = TREE_EXTENSIONS.iter()
+        .chain(fileio::COMPRESSED_EXTENSIONS)
+        .copied()
+        .collect();
     let result = app
         .dialog()
         .file()
+        .add_filter("Tree files", &tree_exts)
+        .add_filter("All files", &["*"])
         .blocking_pick_file();
 
     match result {
@@ -338,9 +503,7 @@ async fn pick_tree_file(app: tauri::AppHandle) -> Result<Option<serde_json::Valu
                 .and_then(|n| n.to_str())
                 .unwrap_or("tree")
                 .to_string();
-            let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-            let path_str = path.to_string_lossy().to_string();
-            Ok(Some(serde_json::json!({ "name": name, "content": content, "path": path_str })))
+            Ok(Some(serde_json::json!({ "name": name, "path": path.to_string_lossy() })))
         }
     }
 }
@@ -349,25 +512,33 @@ async fn pick_tree_file(app: tauri::AppHandle) -> Result<Option<serde_json::Valu
 /// reads the selected file, and returns `{"name": "...", "content": "..."}` to JS.
 /// Returns `null` if the user cancels.
 #[tauri::command]
-async fn pick_annot_file(app: tauri::AppHandle) -> Result<Option<serde_json::Value>, String> {
+async fn pick_annot_file(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+) -> Result<Option<serde_json::Value>, fileio::ReadError> {
+    let annot_exts: Vec<&str> = ["csv", "tsv", "txt"].iter()
+        .chain(fileio::COMPRESSED_EXTENSIONS)
+        .copied()
+        .collect();
     let result = app
         .dialog()
         .file()
-        .add_filter("Annotation files", &["csv", "tsv", "txt"])
+        .add_filter("Annotation files", &annot_exts)
         .add_filter("All files", &["*"])
         .blocking_pick_file();
 
     match result {
         None => Ok(None),
         Some(file_path) => {
-            let path = file_path.into_path().map_err(|e| e.to_string())?;
+            let path = file_path.into_path().map_err(|e| fileio::ReadError::io(std::path::Path::new("selected file"), e))?;
             let name = path
                 .file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("annotations")
                 .to_string();
-            let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-            Ok(Some(serde_json::json!({ "name": name, "content": content })))
+            let job  = LoadJob::start(&app, &window);
+            let file = fileio::read_text(&path, |read, total| job.progress(read, total))?;
+            Ok(Some(serde_json::json!({ "name": name, "content": file.text, "encoding": file.encoding })))
         }
     }
 }
@@ -415,12 +586,458 @@ async fn save_file(
     }
 }
 
-/// Reads a file from the given absolute path and returns its content as a string.
+/// Writes a tree (a node table built by the frontend) as Newick or NEXUS
+/// straight to a file chosen in a native save dialog, so large exports never
+/// go through IPC as one string.
+///
+/// Returns `true` if the file was saved, `false` if the user cancelled.
+#[tauri::command]
+async fn export_tree(
+    app: tauri::AppHandle,
+    filename: String,
+    filter_name: String,
+    extensions: Vec<String>,
+    tree: tree::Tree,
+    options: writer::WriteOptions,
+) -> Result<bool, String> {
+    let ext_refs: Vec<&str> = extensions.iter().map(|s| s.as_str()).collect();
+    let result = app
+        .dialog()
+        .file()
+        .set_file_name(&filename)
+        .add_filter(&filter_name, &ext_refs)
+        .blocking_save_file();
+
+    match result {
+        None => Ok(false),
+        Some(path) => {
+            use std::io::Write;
+            let path = path.into_path().map_err(|e| e.to_string())?;
+            let file = std::fs::File::create(&path).map_err(|e| e.to_string())?;
+            let mut out = std::io::BufWriter::new(file);
+            writer::write_tree(&mut out, &tree, &options).map_err(|e| e.to_string())?;
+            out.flush().map_err(|e| e.to_string())?;
+            Ok(true)
+        }
+    }
+}
+
+/// Runs the root-to-tip regression of the RTT panel's tips against their
+/// dates and saves it: a CSV of every tip with its fitted value, residual
+/// and z-score at the path chosen in a native save dialog, then a plain-text
+/// summary of the rate, TMRCA, r, R² and outliers at the path chosen in a
+/// second one (offered as `name.txt` beside `name.csv`; cancelling it keeps
+/// just the CSV). `date_key` is the date annotation, named in the summary.
+///
+/// Returns the regression, or `None` if the user cancelled.
+#[tauri::command]
+async fn export_rtt_report(
+    app: tauri::AppHandle,
+    filename: String,
+    points: Vec<rtt::TipPoint>,
+    date_key: Option<String>,
+) -> Result<Option<rtt::Report>, String> {
+    use std::io::Write;
+    let report = rtt::regress(&points)?;
+    let Some(path) = app
+        .dialog()
+        .file()
+        .set_file_name(&filename)
+        .add_filter("CSV", &["csv"])
+        .blocking_save_file()
+    else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| e.to_string())?;
+    let mut csv = std::io::BufWriter::new(std::fs::File::create(&path).map_err(|e| e.to_string())?);
+    rtt::write_csv(&mut csv, &points, &report).and_then(|()| csv.flush()).map_err(|e| e.to_string())?;
+    let mut dialog = app.dialog().file().add_filter("Text", &["txt"]);
+    if let Some(dir) = path.parent() {
+        dialog = dialog.set_directory(dir);
+    }
+    if let Some(stem) = path.file_stem() {
+        dialog = dialog.set_file_name(format!("{}.txt", stem.to_string_lossy()));
+    }
+    let Some(summary_path) = dialog.blocking_save_file() else {
+        return Ok(Some(report));
+    };
+    let summary_path = summary_path.into_path().map_err(|e| e.to_string())?;
+    let summary = std::fs::File::create(summary_path).map_err(|e| e.to_string())?;
+    let mut summary = std::io::BufWriter::new(summary);
+    rtt::write_summary(&mut summary, &points, &report, date_key.as_deref())
+        .and_then(|()| summary.flush())
+        .map_err(|e| e.to_string())?;
+    Ok(Some(report))
+}
+
+/// Date-randomisation test of temporal signal in the RTT panel's tips: the
+/// root-to-tip rate is refitted to `options.replicates` shuffles of the tip
+/// dates, between tips or between same-date clusters. Runs on a blocking
+/// thread as a cancellable job of the calling window, separate from its file
+/// loads, emitting `date-test-progress-{label}` events; `cancel_date_test`
+/// stops it.
+///
+/// Returns the test, or `None` if it was cancelled.
+#[tauri::command]
+async fn date_randomisation_test(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    points: Vec<rtt::TipPoint>,
+    options: rtt::DateTestOptions,
+) -> Result<Option<rtt::DateTest>, String> {
+    let job = LoadJob::start_kind(&app, &window, JobKind::DateTest);
+    tauri::async_runtime::spawn_blocking(move || {
+        rtt::date_randomisation(&points, &options, |done, total| {
+            job.emit("date-test-progress", JobProgress { done, total });
+            !job.is_cancelled()
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Cancels the calling window's running date-randomisation test, if any.
+#[tauri::command]
+fn cancel_date_test(app: tauri::AppHandle, window: tauri::WebviewWindow) {
+    cancel_job(&app, &window, JobKind::DateTest);
+}
+
+/// Reads a file from the given absolute path and returns its content as a string,
+/// decompressing gzip / xz / zstd / bzip2 files and transcoding UTF-16 or legacy
+/// encodings to UTF-8 transparently.
 /// Used by the frontend to load a file that was opened via drag-to-icon or a
 /// file association double-click (the path is emitted via the "open-file" event).
+///
+/// Runs as a cancellable load job: emits `load-progress-{label}` events to the
+/// calling window and stops early when `cancel_load` is invoked.
 #[tauri::command]
-fn read_file_content(path: String) -> Result<String, String> {
-    std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {path}: {e}"))
+async fn read_file_content(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    path: String,
+) -> Result<String, fileio::ReadError> {
+    let job = LoadJob::start(&app, &window);
+    fileio::read_text(std::path::Path::new(&path), |read, total| job.progress(read, total))
+        .map(|file| file.text)
+}
+
+/// Reads a NEXUS or Newick tree file and parses it natively, returning the
+/// compact node table described in `tree.rs` instead of the raw text, plus any
+/// embedded PearTree settings and structured load warnings. The frontend
+/// rebuilds nested nodes from it and hands them to `fromNestedRoot`, skipping
+/// `parseNexus` / `parseNewick`, which freeze the webview on trees with 100k+ tips.
+///
+/// UShER mutation-annotated trees (`.pb`) are recognised by their content
+/// and read from the binary protobuf instead.
+///
+/// Compound support labels (IQ-TREE's `95/100`) are split into separate
+/// annotations, named from the file extension where it says which program
+/// wrote the tree (see `support.rs`).
+///
+/// Only the first tree is parsed. When the file holds several (a BEAST
+/// posterior sample, a bootstrap set) the result also carries the tree index
+/// and the file stays in TreeSets so `get_tree` can fetch the others.
+///
+/// `async` so the read and parse run on a worker thread. Like
+/// `read_file_content` it is a cancellable load job with progress events.
+#[tauri::command]
+async fn parse_tree(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    path: String,
+) -> Result<tree::TreeFile, fileio::ReadError> {
+    let path = std::path::Path::new(&path);
+    let job  = LoadJob::start(&app, &window);
+    let bytes = fileio::read_bytes(path, |read, total| job.progress(read, total))?;
+    if job.is_cancelled() {
+        return Err(fileio::ReadError::cancelled(path));
+    }
+    if usher::is_mat(&bytes) {
+        let tree = usher::parse_mat(&bytes).map_err(|e| fileio::ReadError::parse(path, e))?;
+        app.state::<TreeSets>().0.lock().unwrap().remove(window.label());
+        remember_window_tree(&app, &window, &tree);
+        return Ok(tree::TreeFile { trees: vec![tree], ..Default::default() });
+    }
+    let text = fileio::decode_text(path, bytes)?;
+    let set = treeset::TreeSet::open(text.text)
+        .map_err(|e| fileio::ReadError::parse(path, e))?
+        .with_label_origin(support::LabelOrigin::from_path(path));
+    if set.len() == 0 {
+        return Err(fileio::ReadError::parse(path, newick::ParseError::new(0, "no trees found")));
+    }
+    let mut file = set.tree_file(0).map_err(|e| fileio::ReadError::parse(path, e))?;
+    if job.is_cancelled() {
+        return Err(fileio::ReadError::cancelled(path));
+    }
+    file.encoding = Some(text.encoding);
+    remember_window_tree(&app, &window, &file.trees[0]);
+
+    let mut sets = app.state::<TreeSets>().0.lock().unwrap();
+    if set.len() > 1 {
+        file.index = Some(set.index());
+        sets.insert(window.label().to_string(), Arc::new(set));
+    } else {
+        sets.remove(window.label());
+    }
+    Ok(file)
+}
+
+/// Parses tree `index` (0-based) of the multi-tree file most recently opened
+/// by `parse_tree` in the calling window, from the copy held in memory.
+#[tauri::command]
+async fn get_tree(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    index: usize,
+) -> Result<tree::TreeFile, String> {
+    let set = window_tree_set(&app, &window)?;
+    let file = set.tree_file(index).map_err(|e| e.to_string())?;
+    remember_window_tree(&app, &window, &file.trees[0]);
+    Ok(file)
+}
+
+/// Cancels the calling window's in-flight file load, if any. The load's
+/// command then fails with a `cancelled` ReadError.
+#[tauri::command]
+fn cancel_load(app: tauri::AppHandle, window: tauri::WebviewWindow) {
+    cancel_job(&app, &window, JobKind::Load);
+}
+
+/// Sets the cancel flag of the calling window's job of `kind`, if one runs.
+fn cancel_job(app: &tauri::AppHandle, window: &tauri::WebviewWindow, kind: JobKind) {
+    if let Some(flag) = app.state::<LoadJobs>().0.lock().unwrap().get(&(window.label().to_string(), kind)) {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Builds a consensus of the trees in the calling window's multi-tree file
+/// (skipping the first `burnin` trees) from clades found in at least
+/// `threshold` (0–1) of them, and opens it in a new window.
+#[tauri::command]
+async fn build_consensus(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    threshold: f64,
+    burnin: usize,
+) -> Result<(), String> {
+    let set = window_tree_set(&app, &window)?;
+    let tree = consensus::consensus(&set, burnin, threshold)?;
+    open_tree_in_new_window(&app, tree)
+}
+
+/// Summarises the calling window's multi-tree file (skipping the first
+/// `burnin` trees) onto its maximum clade credibility tree, with mean node
+/// heights and 95% HPD intervals, and opens it in a new window.
+#[tauri::command]
+async fn build_mcc(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    burnin: usize,
+) -> Result<(), String> {
+    let set = window_tree_set(&app, &window)?;
+    let tree = mcc::mcc(&set, burnin)?;
+    open_tree_in_new_window(&app, tree)
+}
+
+/// Re-roots the node table `tree` as `method` asks and returns the new table
+/// with the input row of each output row. Runs off the main thread, so the
+/// Tree menu's rooting actions stay responsive on very large trees.
+/// `branch_keys` are the annotations that describe a branch rather than a
+/// node; they move with their edge when its direction flips.
+#[tauri::command]
+async fn root_tree(
+    tree: tree::Tree,
+    method: rooting::RootMethod,
+    branch_keys: Vec<String>,
+) -> Result<rooting::Rerooted, String> {
+    rooting::reroot(&tree, &method, &branch_keys)
+}
+
+/// Dates the node table `tree` under a strict clock by least squares, from
+/// the decimal-year tip `dates` (one per row), and opens the time tree in a
+/// new window with its date axis set to `date_key`. Returns the clock rate
+/// and root date for the calling window to report.
+#[tauri::command]
+async fn estimate_time_tree(
+    app: tauri::AppHandle,
+    tree: tree::Tree,
+    dates: Vec<Option<f64>>,
+    date_key: String,
+    options: dating::DatingOptions,
+) -> Result<dating::Dated, String> {
+    let mut dated = dating::date_tree(&tree, &dates, &options)?;
+    open_file_in_new_window(&app, tree::TreeFile {
+        trees: vec![std::mem::take(&mut dated.tree)],
+        settings: Some(serde_json::json!({ "axisDateAnnotation": date_key })),
+        ..Default::default()
+    })?;
+    Ok(dated)
+}
+
+/// Asks for an UShER MAT file and opens, in a new window, the subtree around
+/// `samples`: for each, the smallest clade holding at least `size` tips. The
+/// read is a cancellable load job of the calling window, as in `parse_tree`.
+///
+/// Returns `true` if a subtree was opened, `false` if the user cancelled.
+#[tauri::command]
+async fn open_usher_subtree(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    samples: Vec<String>,
+    size: usize,
+) -> Result<bool, String> {
+    let exts: Vec<&str> = std::iter::once("pb").chain(fileio::COMPRESSED_EXTENSIONS.iter().copied()).collect();
+    let Some(file_path) = app.dialog().file().add_filter("UShER trees", &exts).blocking_pick_file() else {
+        return Ok(false);
+    };
+    let path = file_path.into_path().map_err(|e| e.to_string())?;
+    let job = LoadJob::start(&app, &window);
+    let bytes = fileio::read_bytes(&path, |read, total| job.progress(read, total)).map_err(|e| e.to_string())?;
+    if !usher::is_mat(&bytes) {
+        return Err(format!("{} is not an UShER mutation-annotated tree", path.display()));
+    }
+    let tree = usher::parse_mat(&bytes).map_err(|e| fileio::ReadError::parse(&path, e).to_string())?;
+    drop(bytes);
+    let mut subtree = usher::neighbourhood(&tree, &samples, size)?;
+    subtree.name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    open_tree_in_new_window(&app, subtree)?;
+    Ok(true)
+}
+
+/// One side of `compare_trees`: the tree shown in a window (by label, as in
+/// LastFocusedWindow), or the first tree of a file, asked for with a native
+/// picker when `path` is absent.
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum TreeSource {
+    Window { label: String },
+    File { path: Option<String> },
+}
+
+/// A window with a tree to compare, as listed by `tree_windows`.
+#[derive(serde::Serialize)]
+struct TreeWindow {
+    label: String,
+    title: String,
+}
+
+/// Lists the windows showing a tree `compare_trees` can use.
+#[tauri::command]
+fn tree_windows(app: tauri::AppHandle) -> Vec<TreeWindow> {
+    let labels: Vec<String> = app.state::<WindowTrees>().0.lock().unwrap().keys().cloned().collect();
+    let mut windows: Vec<TreeWindow> = labels
+        .into_iter()
+        .map(|label| TreeWindow { title: window_title(&app, &label), label })
+        .collect();
+    windows.sort_by(|a, b| a.title.cmp(&b.title));
+    windows
+}
+
+/// Drops the calling window's tree from `compare_trees`' reach. The frontend
+/// calls it after loading a tree Rust never saw, so comparisons cannot use
+/// the tree the window showed before.
+#[tauri::command]
+fn forget_window_tree(app: tauri::AppHandle, window: tauri::WebviewWindow) {
+    app.state::<WindowTrees>().0.lock().unwrap().remove(window.label());
+}
+
+/// Compares two trees on the taxa they share: Robinson–Foulds (plain and
+/// normalised), branch-score and quartet distances, with the taxa found in
+/// only one of them (see `distance.rs`). Files are read as cancellable load
+/// jobs of the calling window.
+///
+/// Returns `None` if the user cancelled a file picker or read.
+#[tauri::command]
+async fn compare_trees(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    first: TreeSource,
+    second: TreeSource,
+) -> Result<Option<distance::Comparison>, String> {
+    let Some((first_name, first)) = source_tree(&app, &window, first)? else {
+        return Ok(None);
+    };
+    let Some((second_name, second)) = source_tree(&app, &window, second)? else {
+        return Ok(None);
+    };
+    distance::compare(&first, &second, [first_name, second_name]).map(Some)
+}
+
+/// The name and tree of one side of `compare_trees`; `None` if the user
+/// cancelled the file picker or the read.
+fn source_tree(
+    app: &tauri::AppHandle,
+    window: &tauri::WebviewWindow,
+    source: TreeSource,
+) -> Result<Option<(String, Arc<tree::Tree>)>, String> {
+    let path = match source {
+        TreeSource::Window { label } => {
+            let tree = app.state::<WindowTrees>().0.lock().unwrap()
+                .get(&label)
+                .cloned()
+                .ok_or_else(|| format!("{} has no tree to compare", window_title(app, &label)))?;
+            return Ok(Some((window_title(app, &label), tree)));
+        }
+        TreeSource::File { path: Some(path) } => std::path::PathBuf::from(path),
+        TreeSource::File { path: None } => {
+            let exts: Vec<&str> = TREE_EXTENSIONS.iter().chain(fileio::COMPRESSED_EXTENSIONS).copied().collect();
+            let Some(file_path) = app.dialog().file().add_filter("Tree files", &exts).blocking_pick_file() else {
+                return Ok(None);
+            };
+            file_path.into_path().map_err(|e| e.to_string())?
+        }
+    };
+    let job = LoadJob::start(app, window);
+    let bytes = match fileio::read_bytes(&path, |read, total| job.progress(read, total)) {
+        Ok(bytes) => bytes,
+        Err(_) if job.is_cancelled() => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    let tree = if usher::is_mat(&bytes) {
+        usher::parse_mat(&bytes)
+    } else {
+        let text = fileio::decode_text(&path, bytes).map_err(|e| e.to_string())?;
+        treeset::TreeSet::open(text.text).and_then(|set| set.tree(0))
+    }
+    .map_err(|e| fileio::ReadError::parse(&path, e).to_string())?;
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+    Ok(Some((name, Arc::new(tree))))
+}
+
+/// The title of window `label`, or the label if it has none.
+fn window_title(app: &tauri::AppHandle, label: &str) -> String {
+    app.get_webview_window(label)
+        .and_then(|w| w.title().ok())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| label.to_string())
+}
+
+/// The multi-tree file open in `window`.
+fn window_tree_set(
+    app: &tauri::AppHandle,
+    window: &tauri::WebviewWindow,
+) -> Result<Arc<treeset::TreeSet>, String> {
+    app.state::<TreeSets>().0.lock().unwrap()
+        .get(window.label())
+        .cloned()
+        .ok_or_else(|| "No multi-tree file is open in this window".to_string())
+}
+
+/// Records `tree` as the tree `window` is showing, for `compare_trees`.
+fn remember_window_tree(app: &tauri::AppHandle, window: &tauri::WebviewWindow, tree: &tree::Tree) {
+    app.state::<WindowTrees>().0.lock().unwrap().insert(window.label().to_string(), Arc::new(tree.clone()));
+}
+
+/// Opens a tree computed in Rust in a new window, via PendingTrees.
+fn open_tree_in_new_window(app: &tauri::AppHandle, tree: tree::Tree) -> Result<(), String> {
+    open_file_in_new_window(app, tree::TreeFile { trees: vec![tree], ..Default::default() })
+}
+
+/// As `open_tree_in_new_window`, with the file's settings and warnings.
+fn open_file_in_new_window(app: &tauri::AppHandle, file: tree::TreeFile) -> Result<(), String> {
+    open_window(app, |label| {
+        app.state::<PendingTrees>().0.lock().unwrap().insert(label.to_string(), file);
+    })
 }
 
 /// Creates a new PearTree window. If `file_path` is provided the path is stored
@@ -428,15 +1045,24 @@ fn read_file_content(path: String) -> Result<String, String> {
 /// via `take_pending_file` on startup and loads the tree automatically.
 #[tauri::command]
 fn new_window(app: tauri::AppHandle, file_path: Option<String>) -> Result<(), String> {
+    open_window(&app, |label| {
+        if let Some(path) = file_path {
+            app.state::<PendingFiles>().0.lock().unwrap().insert(label.to_string(), path);
+        }
+    })
+}
+
+/// Creates a window with a fresh label. `before_build` receives the label
+/// before the window exists, so anything it stores for the window is in place
+/// by the time the window's JS asks for it.
+fn open_window(app: &tauri::AppHandle, before_build: impl FnOnce(&str)) -> Result<(), String> {
     let n = app.state::<WindowCounter>().0.fetch_add(1, Ordering::SeqCst);
     let label = format!("window-{n}");
 
-    if let Some(path) = file_path {
-        app.state::<PendingFiles>().0.lock().unwrap().insert(label.clone(), path);
-    }
+    before_build(&label);
 
     let win = tauri::WebviewWindowBuilder::new(
-        &app,
+        app,
         &label,
         tauri::WebviewUrl::App("peartree/peartree-tauri.html".into()),
     )
@@ -446,14 +1072,17 @@ fn new_window(app: tauri::AppHandle, file_path: Option<String>) -> Result<(), St
     .build()
     .map_err(|e| e.to_string())?;
 
-    // Track focus so app.on_menu_event can route to the right window.
+    // Track focus so app.on_menu_event can route to the right window, and
+    // drop the window's state when it closes.
     {
         let app_h = app.clone();
         let lbl   = label.clone();
-        win.on_window_event(move |event| {
-            if let tauri::WindowEvent::Focused(true) = event {
+        win.on_window_event(move |event| match event {
+            tauri::WindowEvent::Focused(true) => {
                 *app_h.state::<LastFocusedWindow>().0.lock().unwrap() = lbl.clone();
             }
+            tauri::WindowEvent::Destroyed => forget_window(&app_h, &lbl),
+            _ => {}
         });
     }
 
@@ -464,6 +1093,14 @@ fn new_window(app: tauri::AppHandle, file_path: Option<String>) -> Result<(), St
     Ok(())
 }
 
+/// Drops per-window state (open tree sets, untaken pending trees, the shown
+/// tree) when a window is closed.
+fn forget_window(app: &tauri::AppHandle, label: &str) {
+    app.state::<TreeSets>().0.lock().unwrap().remove(label);
+    app.state::<WindowTrees>().0.lock().unwrap().remove(label);
+    app.state::<PendingTrees>().0.lock().unwrap().remove(label);
+}
+
 /// Open a file in a fresh PearTree window (multi-document behaviour).
 /// If the file was already captured as the cold-start pending file for `main`,
 /// skip creating a duplicate window.
@@ -509,6 +1146,20 @@ fn take_pending_file(
     app.state::<PendingFiles>().0.lock().unwrap().remove(label)
 }
 
+/// Called by the JS adapter on startup to show a tree passed to the window
+/// by a Rust command such as `build_consensus`.
+#[tauri::command]
+fn take_pending_tree(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+) -> Option<tree::TreeFile> {
+    let file = app.state::<PendingTrees>().0.lock().unwrap().remove(window.label());
+    if let Some(file) = &file {
+        remember_window_tree(&app, &window, &file.trees[0]);
+    }
+    file
+}
+
 /// Triggers the native OS print dialog for the calling window.
 /// Called by peartree-tauri.js instead of window.print(), which is
 /// unreliable inside WKWebView on macOS.
@@ -564,7 +1215,7 @@ async fn install_update(app: tauri::AppHandle) -> Result<(), String> {
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let app = tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![set_menu_item_enabled, set_menu_item_text, pick_tree_file, pick_annot_file, save_file, read_file_content, new_window, take_pending_file, trigger_print, check_for_updates, install_update])
+        .invoke_handler(tauri::generate_handler![set_menu_item_enabled, set_menu_item_text, pick_tree_file, pick_annot_file, save_file, export_tree, export_rtt_report, date_randomisation_test, read_file_content, parse_tree, get_tree, cancel_load, cancel_date_test, build_consensus, build_mcc, root_tree, estimate_time_tree, tree_windows, forget_window_tree, compare_trees, open_usher_subtree, new_window, take_pending_file, take_pending_tree, trigger_print, check_for_updates, install_update])
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_deep_link::init())
@@ -589,17 +1240,23 @@ pub fn run() {
 
             app.manage(WindowCounter(AtomicU32::new(0)));
             app.manage(PendingFiles(Mutex::new(HashMap::new())));
+            app.manage(PendingTrees(Mutex::new(HashMap::new())));
             app.manage(PendingUpdate(Mutex::new(None)));
+            app.manage(LoadJobs(Mutex::new(HashMap::new())));
+            app.manage(TreeSets(Mutex::new(HashMap::new())));
+            app.manage(WindowTrees(Mutex::new(HashMap::new())));
             app.manage(MenuItems(Mutex::new(item_map)));
             app.manage(LastFocusedWindow(Mutex::new("main".to_string())));
 
-            // Track focus on the main window.
+            // Track focus on the main window (and clean up when it closes).
             {
                 let app_h = app.handle().clone();
-                main_win.on_window_event(move |event| {
-                    if let tauri::WindowEvent::Focused(true) = event {
+                main_win.on_window_event(move |event| match event {
+                    tauri::WindowEvent::Focused(true) => {
                         *app_h.state::<LastFocusedWindow>().0.lock().unwrap() = "main".to_string();
                     }
+                    tauri::WindowEvent::Destroyed => forget_window(&app_h, "main"),
+                    _ => {}
                 });
             }
 
@@ -0,0 +1,179 @@
+//! Maximum clade credibility (MCC) summary of a posterior tree sample, as
+//! BEAST's TreeAnnotator produces it.
+//!
+//! Three passes over the trees after burn-in: count clades; pick the tree
+//! whose clades have the highest product of frequencies; then, for every
+//! clade of that tree, gather node heights and annotations from each sampled
+//! tree containing it. Node heights are set to their mean.
+//!
+//! Annotations use TreeAnnotator's names so the annotation schema groups them
+//! as it does for TreeAnnotator output: `posterior`, and for heights and any
+//! numeric annotation `x`, the mean as `x` plus `x_median`, `x_95%_HPD` and
+//! `x_range`. `height_95%_HPD` is what the renderer draws as node bars and
+//! shows in `CAL_DATE_HPD_KEY` labels. Discrete annotations give the most
+//! probable value as `x` with `x.prob`, `x.set` and `x.set.prob`.
+
+use crate::clades::{self, Clade, Taxa};
+use crate::tree::Tree;
+use crate::treeset::TreeSet;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Probability mass of the highest posterior density interval.
+const HPD_LEVEL: f64 = 0.95;
+
+/// Values collected for one clade of the MCC tree across the sample.
+#[derive(Default)]
+struct NodeSummary {
+    heights: Vec<f64>,
+    numeric: BTreeMap<String, Vec<f64>>,
+    discrete: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Last tree sampled, so a clade repeated by unary nodes is read once.
+    last_tree: Option<usize>,
+}
+
+/// Summarises trees `burnin..` of `set` onto their MCC tree.
+pub fn mcc(set: &TreeSet, burnin: usize) -> Result<Tree, String> {
+    if burnin >= set.len() {
+        return Err(format!("burn-in of {burnin} leaves none of the {} trees", set.len()));
+    }
+    let read = |i: usize| set.tree(i).map_err(|e| format!("tree {}: {e}", i + 1));
+    let taxa = Taxa::from_tree(&read(burnin)?, burnin)?;
+    let n_trees = (set.len() - burnin) as f64;
+
+    // Pass 1: clade frequencies.
+    let mut counts: HashMap<Clade, usize> = HashMap::new();
+    for i in burnin..set.len() {
+        for clade in internal_clades(&taxa, &read(i)?, i)? {
+            *counts.entry(clade).or_default() += 1;
+        }
+    }
+
+    // Pass 2: the tree with the highest log clade credibility.
+    let mut best = (f64::NEG_INFINITY, burnin);
+    for i in burnin..set.len() {
+        let score: f64 = internal_clades(&taxa, &read(i)?, i)?
+            .iter()
+            .map(|c| (counts[c] as f64 / n_trees).ln())
+            .sum();
+        if score > best.0 {
+            best = (score, i);
+        }
+    }
+
+    // Pass 3: heights and annotations for each clade of the MCC tree.
+    let mut tree = read(best.1)?;
+    let target = taxa.clades(&tree, best.1)?;
+    let mut summaries: HashMap<Clade, NodeSummary> =
+        target.iter().map(|c| (c.clone(), NodeSummary::default())).collect();
+    for i in burnin..set.len() {
+        let sample = read(i)?;
+        let heights = node_heights(&sample);
+        for (n, clade) in taxa.clades(&sample, i)?.into_iter().enumerate() {
+            let Some(s) = summaries.get_mut(&clade) else { continue };
+            if s.last_tree == Some(i) {
+                continue;
+            }
+            s.last_tree = Some(i);
+            s.heights.push(heights[n]);
+            for (key, value) in &sample.nodes[n].annotations {
+                match value {
+                    Value::Number(x) if key != "height" => {
+                        s.numeric.entry(key.clone()).or_default().extend(x.as_f64());
+                    }
+                    Value::String(x) => {
+                        *s.discrete.entry(key.clone()).or_default().entry(x.clone()).or_default() += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut heights = vec![0.0; tree.nodes.len()];
+    for (n, clade) in target.iter().enumerate() {
+        let s = &summaries[clade];
+        let node = &mut tree.nodes[n];
+        node.annotations = Map::new();
+        if clades::size(clade) > 1 {
+            node.annotations.insert("posterior".into(), Value::from(counts[clade] as f64 / n_trees));
+        }
+        heights[n] = summarise_numeric(&mut node.annotations, "height", s.heights.clone());
+        for (key, values) in &s.numeric {
+            summarise_numeric(&mut node.annotations, key, values.clone());
+        }
+        for (key, values) in &s.discrete {
+            summarise_discrete(&mut node.annotations, key, values);
+        }
+    }
+    for n in 0..tree.nodes.len() {
+        tree.nodes[n].length = tree.nodes[n].parent.map(|p| (heights[p] - heights[n]).max(0.0));
+    }
+    tree.rooted = Some(true);
+    tree.name = Some(match &tree.name {
+        Some(name) => format!("MCC tree ({name})"),
+        None => format!("MCC tree (tree {})", best.1 + 1),
+    });
+    Ok(tree)
+}
+
+/// The distinct clades of a tree's internal nodes.
+fn internal_clades(taxa: &Taxa, tree: &Tree, i: usize) -> Result<Vec<Clade>, String> {
+    let mut found: Vec<Clade> = taxa.clades(tree, i)?.into_iter().filter(|c| clades::size(c) > 1).collect();
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+/// Height of each node above the most distant tip (missing lengths count as 0).
+fn node_heights(tree: &Tree) -> Vec<f64> {
+    let mut depth = vec![0.0; tree.nodes.len()];
+    for (n, node) in tree.nodes.iter().enumerate() {
+        if let Some(p) = node.parent {
+            depth[n] = depth[p] + node.length.unwrap_or(0.0);
+        }
+    }
+    let max = depth.iter().copied().fold(0.0, f64::max);
+    depth.iter().map(|d| max - d).collect()
+}
+
+/// Writes `key` (mean), `key_median`, `key_95%_HPD` and `key_range`, and
+/// returns the mean.
+fn summarise_numeric(annots: &mut Map<String, Value>, key: &str, mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    let mean = values.iter().sum::<f64>() / n as f64;
+    let median = if n % 2 == 1 { values[n / 2] } else { (values[n / 2 - 1] + values[n / 2]) / 2.0 };
+    let (lo, hi) = hpd(&values);
+    annots.insert(key.to_string(), Value::from(mean));
+    annots.insert(format!("{key}_median"), Value::from(median));
+    annots.insert(format!("{key}_95%_HPD"), Value::from(vec![lo, hi]));
+    annots.insert(format!("{key}_range"), Value::from(vec![values[0], values[n - 1]]));
+    mean
+}
+
+/// Shortest interval holding `HPD_LEVEL` of the sorted `values`.
+fn hpd(values: &[f64]) -> (f64, f64) {
+    let n = values.len();
+    let k = ((HPD_LEVEL * n as f64).round() as usize).clamp(1, n);
+    (0..=n - k)
+        .map(|i| (values[i], values[i + k - 1]))
+        .min_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)))
+        .unwrap_or((values[0], values[n - 1]))
+}
+
+/// Writes the most probable value as `key` with `key.prob`, and all values
+/// seen (most probable first) as `key.set` and `key.set.prob`.
+fn summarise_discrete(annots: &mut Map<String, Value>, key: &str, counts: &BTreeMap<String, usize>) {
+    let total: usize = counts.values().sum();
+    let mut ranked: Vec<(&String, usize)> = counts.iter().map(|(v, &c)| (v, c)).collect();
+    ranked.sort_by_key(|&(_, c)| std::cmp::Reverse(c));
+    let prob = |c: usize| Value::from(c as f64 / total as f64);
+    annots.insert(key.to_string(), Value::from(ranked[0].0.as_str()));
+    annots.insert(format!("{key}.prob"), prob(ranked[0].1));
+    annots.insert(format!("{key}.set"), ranked.iter().map(|(v, _)| Value::from(v.as_str())).collect());
+    annots.insert(format!("{key}.set.prob"), ranked.iter().map(|&(_, c)| prob(c)).collect());
+}
@@ -0,0 +1,572 @@
+//! Native Newick parser.
+//!
+//! Handles quoted labels (`'...'` with `''` escapes, and `"..."`), `[...]`
+//! comments anywhere whitespace is allowed, BEAST-style `[&key=value,...]`
+//! annotations (including `{...}` arrays), NHX `[&&NHX:key=value:...]`
+//! annotations, RAxML `:0.1[100]` branch support and missing branch lengths. The
+//! parser is iterative, so caterpillar trees with 100k+ tips cannot overflow
+//! the stack.
+
+use crate::tree::Tree;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use std::fmt;
+
+/// A Newick syntax error, located by byte offset into the parsed text.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(offset: usize, message: impl Into<String>) -> Self {
+        ParseError { offset, message: message.into() }
+    }
+
+    /// An error reported by line and column (both 1-based, column in
+    /// characters), as XML and JSON parsers give them.
+    pub(crate) fn at_line(text: &str, line: usize, column: usize, message: impl Into<String>) -> Self {
+        let line_start: usize = text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+        let col: usize = text[line_start..].chars().take(column.saturating_sub(1)).map(char::len_utf8).sum();
+        ParseError::new(line_start + col, message)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Byte offsets at which each tree of a multi-tree Newick file (one tree per
+/// `;`) starts. Trees are located without being built; read one with
+/// `Parser::new(text, offset).parse_tree()`.
+pub fn tree_offsets(text: &str) -> Result<Vec<usize>, ParseError> {
+    let mut offsets = Vec::new();
+    let mut parser = Parser::new(text, 0);
+    loop {
+        while parser.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            parser.pos += 1;
+        }
+        let start = parser.pos;
+        // Trailing comments after the last tree are not a tree.
+        parser.skip_ws_and_comments(&mut Map::new())?;
+        if parser.peek().is_none() {
+            return Ok(offsets);
+        }
+        offsets.push(start);
+        parser.skip_tree()?;
+    }
+}
+
+/// Cursor over the Newick text. Exposed to the other readers in the crate so
+/// NEXUS `tree` statements can be parsed in place.
+pub(crate) struct Parser<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(text: &'a str, pos: usize) -> Self {
+        Parser { text, pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    /// Parses one tree starting at the cursor and leaves the cursor just after
+    /// its terminating `;` (or at end of input when the `;` is missing).
+    pub(crate) fn parse_tree(&mut self) -> Result<Tree, ParseError> {
+        let mut tree = Tree::default();
+        let mut leading = Map::new();
+        self.skip_ws_and_comments(&mut leading)?;
+        if matches!(self.peek(), None | Some(b';')) {
+            return Err(ParseError::new(self.pos, "no tree found"));
+        }
+        // [&R] / [&U] arrive as a bare "R" / "U" annotation key.
+        if leading.remove("R").is_some() {
+            tree.rooted = Some(true);
+        } else if leading.remove("U").is_some() {
+            tree.rooted = Some(false);
+        }
+
+        let mut open: Vec<usize> = Vec::new();
+        let mut expecting_node = true;
+        loop {
+            if expecting_node {
+                let mut annotations = Map::new();
+                self.skip_ws_and_comments(&mut annotations)?;
+                let n = tree.push_node(open.last().copied());
+                tree.nodes[n].annotations = annotations;
+                if self.peek() == Some(b'(') {
+                    self.pos += 1;
+                    open.push(n);
+                    continue;
+                }
+                self.parse_node_suffix(&mut tree, n)?;
+                expecting_node = false;
+                continue;
+            }
+
+            self.skip_ws_and_comments(&mut Map::new())?;
+            match self.peek() {
+                Some(b',') if !open.is_empty() => {
+                    self.pos += 1;
+                    expecting_node = true;
+                }
+                Some(b')') => {
+                    let n = open
+                        .pop()
+                        .ok_or_else(|| ParseError::new(self.pos, "unbalanced ')'"))?;
+                    self.pos += 1;
+                    self.parse_node_suffix(&mut tree, n)?;
+                }
+                Some(b';') if open.is_empty() => {
+                    self.pos += 1;
+                    break;
+                }
+                None if open.is_empty() => break,
+                None => return Err(ParseError::new(self.pos, "unexpected end of tree: missing ')'")),
+                Some(_) => {
+                    let c = self.text[self.pos..].chars().next().unwrap_or_default();
+                    return Err(ParseError::new(self.pos, format!("unexpected character '{c}'")));
+                }
+            }
+        }
+
+        if !leading.is_empty() {
+            let root = &mut tree.nodes[0].annotations;
+            for (k, v) in leading {
+                root.entry(k).or_insert(v);
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Advances past the tree at the cursor without building it: to just after
+    /// its `;`, or to end of input. Quoted labels and comments are honoured.
+    pub(crate) fn skip_tree(&mut self) -> Result<(), ParseError> {
+        // Quotes only open a quoted label where a label may start.
+        let mut label_start = true;
+        while let Some(c) = self.peek() {
+            match c {
+                b';' => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                b'[' => {
+                    let end = comment_end(self.text, self.pos)
+                        .ok_or_else(|| ParseError::new(self.pos, "unterminated comment"))?;
+                    self.pos = end + 1;
+                    continue;
+                }
+                b'\'' | b'"' if label_start => {
+                    self.parse_quoted(c)?;
+                    label_start = false;
+                    continue;
+                }
+                b'(' | b',' | b')' => label_start = true,
+                c if c.is_ascii_whitespace() => {}
+                _ => label_start = false,
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// Reads the label, comments and `:length` that may follow a tip or a `)`.
+    fn parse_node_suffix(&mut self, tree: &mut Tree, n: usize) -> Result<(), ParseError> {
+        let node = &mut tree.nodes[n];
+        self.skip_ws_and_comments(&mut node.annotations)?;
+        if let Some(label) = self.parse_label()? {
+            node.label = Some(label);
+        }
+        self.skip_ws_and_comments(&mut node.annotations)?;
+        if self.peek() == Some(b':') {
+            self.pos += 1;
+            self.skip_ws_and_comments(&mut node.annotations)?;
+            node.length = self.parse_length()?;
+            self.parse_branch_support(&mut node.annotations);
+            self.skip_ws_and_comments(&mut node.annotations)?;
+        }
+        Ok(())
+    }
+
+    /// RAxML's `BranchLabels` trees give support as a bare `[100]` comment
+    /// after the branch length. Keeps it as a `support` annotation.
+    fn parse_branch_support(&mut self, annotations: &mut Map<String, Value>) {
+        let rest = self.text[self.pos..].trim_start();
+        let Some(body) = rest.strip_prefix('[') else { return };
+        let Some(end) = body.find(']') else { return };
+        if body[..end].trim().parse::<f64>().is_ok() {
+            annotations.insert("support".into(), typed_value(body[..end].trim()));
+            self.pos = self.text.len() - body.len() + end + 1;
+        }
+    }
+
+    fn parse_label(&mut self) -> Result<Option<String>, ParseError> {
+        match self.peek() {
+            Some(q @ (b'\'' | b'"')) => self.parse_quoted(q).map(Some),
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                Ok((self.pos > start).then(|| self.text[start..self.pos].to_string()))
+            }
+        }
+    }
+
+    /// Reads a quoted string; a doubled quote character stands for itself.
+    pub(crate) fn parse_quoted(&mut self, quote: u8) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(end) = rest.bytes().position(|c| c == quote) else {
+                return Err(ParseError::new(start, "unterminated quoted label"));
+            };
+            out.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if self.peek() == Some(quote) {
+                out.push(quote as char);
+                self.pos += 1;
+            } else {
+                return Ok(out);
+            }
+        }
+    }
+
+    fn parse_length(&mut self) -> Result<Option<f64>, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Ok(None);
+        }
+        let raw = &self.text[start..self.pos];
+        raw.parse::<f64>()
+            .map(Some)
+            .map_err(|_| ParseError::new(start, format!("invalid branch length '{raw}'")))
+    }
+
+    /// Skips whitespace and `[...]` comments. Comments starting with `&` are
+    /// parsed as annotations and merged into `annotations`; others are dropped.
+    pub(crate) fn skip_ws_and_comments(
+        &mut self,
+        annotations: &mut Map<String, Value>,
+    ) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(b'[') => {
+                    let start = self.pos;
+                    let end = comment_end(self.text, start)
+                        .ok_or_else(|| ParseError::new(start, "unterminated comment"))?;
+                    let body = &self.text[start + 1..end];
+                    if let Some(annot) = body.strip_prefix('&') {
+                        parse_annotations(annot, annotations)
+                            .map_err(|e| ParseError::new(start + 2 + e.offset, e.message))?;
+                    }
+                    self.pos = end + 1;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Returns the byte index of the `]` closing the comment that opens at
+/// `start`. Nested `[...]` and quoted strings are skipped, so JSON payloads
+/// such as `[peartree={"a":[1,2]}]` and values such as `[&name='a]b']` stay
+/// in one piece. Backslash escapes apply in double quotes only, as in
+/// `AnnotParser::parse_string`. A `'` is only a quote where an annotation
+/// value can start (after `=`, `{` or `,` in a `[&...]` comment), so plain
+/// comments such as `[Bob's tree]` still end at their `]`.
+pub(crate) fn comment_end(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let annotation = bytes.get(start + 1) == Some(&b'&');
+    let mut depth = 0usize;
+    let mut prev = b'[';
+    let mut i = start;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            b'\'' if !(annotation && matches!(prev, b'=' | b'{' | b',')) => {}
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if quote == b'"' && bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        if !c.is_ascii_whitespace() {
+            prev = c;
+        }
+        i += 1;
+    }
+    None
+}
+
+fn is_delimiter(c: u8) -> bool {
+    matches!(c, b'(' | b')' | b'[' | b']' | b',' | b':' | b';') || c.is_ascii_whitespace()
+}
+
+/// Parses the body of a `[&...]` comment (without the `&`) into `out`.
+/// Values are typed: numbers become JSON numbers, `{a,b}` becomes an array
+/// and everything else a string. A key with no `=` is stored as `true`.
+/// Keys and strings may be double-quoted, with `\"` and `\\` escaped.
+/// An NHX body (`&NHX:...`) is read with NHX's `:`-separated syntax.
+pub(crate) fn parse_annotations(body: &str, out: &mut Map<String, Value>) -> Result<(), ParseError> {
+    if let Some(nhx) = body.strip_prefix("&NHX") {
+        parse_nhx(nhx, out);
+        return Ok(());
+    }
+    let mut p = AnnotParser { text: body, pos: 0 };
+    loop {
+        p.skip_ws();
+        if p.pos >= body.len() {
+            return Ok(());
+        }
+        let key_start = p.pos;
+        let key = if p.peek() == Some(b'"') {
+            let key = p.parse_string()?;
+            p.skip_ws();
+            key
+        } else {
+            while let Some(c) = p.peek() {
+                if c == b'=' || c == b',' {
+                    break;
+                }
+                p.pos += 1;
+            }
+            body[key_start..p.pos].trim().to_string()
+        };
+        if key.is_empty() {
+            return Err(ParseError::new(key_start, "missing annotation name"));
+        }
+        let value = if p.peek() == Some(b'=') {
+            p.pos += 1;
+            p.parse_value()?
+        } else {
+            Value::Bool(true)
+        };
+        out.insert(key, value);
+        p.skip_ws();
+        match p.peek() {
+            Some(b',') => p.pos += 1,
+            None => return Ok(()),
+            Some(_) => return Err(ParseError::new(p.pos, "expected ',' between annotations")),
+        }
+    }
+}
+
+/// Parses the `:key=value:key=value` body of an NHX comment (`[&&NHX:...]`).
+/// NHX values cannot contain `:`, so there is nothing to escape; a `{a,b}`
+/// value is read as an array, as PearTree writes them.
+fn parse_nhx(body: &str, out: &mut Map<String, Value>) {
+    for field in body.split(':').map(str::trim).filter(|f| !f.is_empty()) {
+        match field.split_once('=') {
+            Some((key, value)) if value.trim().starts_with('{') => {
+                out.insert(key.trim().to_string(), parse_annotation_value(value.trim()))
+            }
+            Some((key, value)) => out.insert(key.trim().to_string(), typed_value(value.trim())),
+            None => out.insert(field.to_string(), Value::Bool(true)),
+        };
+    }
+}
+
+struct AnnotParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl AnnotParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads a quoted string. Inside double quotes `\"` and `\\` stand for
+    /// `"` and `\`, and any other backslash is kept; single quotes have no
+    /// escapes.
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        let bytes = self.text.as_bytes();
+        let start = self.pos;
+        let quote = bytes[start];
+        let mut out = String::new();
+        let (mut run, mut i) = (start + 1, start + 1);
+        loop {
+            match bytes.get(i) {
+                None => return Err(ParseError::new(start, "unterminated quoted annotation")),
+                Some(&c) if c == quote => {
+                    out.push_str(&self.text[run..i]);
+                    self.pos = i + 1;
+                    return Ok(out);
+                }
+                Some(b'\\') if quote == b'"' && matches!(bytes.get(i + 1), Some(b'"' | b'\\')) => {
+                    out.push_str(&self.text[run..i]);
+                    run = i + 1;
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.peek() == Some(b'}') {
+                        self.pos += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.parse_value()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {}
+                        _ => return Err(ParseError::new(self.pos, "unterminated '{' in annotation")),
+                    }
+                }
+            }
+            Some(b'"' | b'\'') => self.parse_string().map(Value::String),
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c == b',' || c == b'}' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                Ok(typed_value(self.text[start..self.pos].trim()))
+            }
+        }
+    }
+}
+
+/// Parses one annotation value as it would appear after `key=`, so `{a,b}`
+/// becomes an array. Anything that does not parse whole is kept as a string.
+pub(crate) fn parse_annotation_value(raw: &str) -> Value {
+    let mut p = AnnotParser { text: raw, pos: 0 };
+    match p.parse_value() {
+        Ok(value) if p.pos == raw.len() => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Converts a bare annotation token to a JSON number when it parses as an
+/// integer or a finite float, otherwise keeps it as a string. Integers stay
+/// integers so they are written back out without a trailing `.0`.
+pub(crate) fn typed_value(raw: &str) -> Value {
+    if let Ok(i) = raw.parse::<i64>() {
+        return Value::from(i);
+    }
+    raw.parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(text: &str) -> Result<Tree, ParseError> {
+        Parser::new(text, 0).parse_tree()
+    }
+
+    fn labels(tree: &Tree) -> Vec<Option<&str>> {
+        tree.nodes.iter().map(|n| n.label.as_deref()).collect()
+    }
+
+    #[test]
+    fn reads_labels_lengths_and_annotations() {
+        let tree = parse("[&R] ((a:1,'b c':2.5)90[&rate=0.1,host=\"bat\",hpd={1,2}]:0.5,d:3);").unwrap();
+        assert_eq!(tree.rooted, Some(true));
+        assert_eq!(labels(&tree), [None, Some("90"), Some("a"), Some("b c"), Some("d")]);
+        assert_eq!(tree.nodes[1].length, Some(0.5));
+        assert_eq!(tree.nodes[3].length, Some(2.5));
+        assert_eq!(tree.nodes[1].annotations["rate"], json!(0.1));
+        assert_eq!(tree.nodes[1].annotations["host"], json!("bat"));
+        assert_eq!(tree.nodes[1].annotations["hpd"], json!([1, 2]));
+    }
+
+    #[test]
+    fn reads_quoted_keys_and_escaped_strings() {
+        let tree = parse(r#"(a[&"odd key"="say \"hi\" \\ there",'k'=1],b);"#).unwrap();
+        let annots = &tree.nodes[1].annotations;
+        assert_eq!(annots["odd key"], json!(r#"say "hi" \ there"#));
+    }
+
+    #[test]
+    fn skips_single_quoted_values_but_not_apostrophes() {
+        let tree = parse("(a[&name='a]b',x={'p]',\"q\"}],b[Bob's tree]);").unwrap();
+        assert_eq!(labels(&tree), [None, Some("a"), Some("b")]);
+        assert_eq!(tree.nodes[1].annotations["name"], json!("a]b"));
+        assert_eq!(tree.nodes[1].annotations["x"], json!(["p]", "q"]));
+    }
+
+    #[test]
+    fn reads_nhx_comments() {
+        let tree = parse("(a:1[&&NHX:S=human:B=90:sets={x,y}],b:2);").unwrap();
+        let annots = &tree.nodes[1].annotations;
+        assert_eq!(annots["S"], json!("human"));
+        assert_eq!(annots["B"], json!(90));
+        assert_eq!(annots["sets"], json!(["x", "y"]));
+    }
+
+    #[test]
+    fn reads_very_deep_trees() {
+        let depth = 100_000;
+        let text = format!("{}a{};", "(".repeat(depth), ",b)".repeat(depth));
+        assert_eq!(parse(&text).unwrap().nodes.len(), 2 * depth + 1);
+    }
+
+    #[test]
+    fn finds_every_tree() {
+        assert_eq!(tree_offsets("(a,b);\n(c,d);\n").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in ["", ";", "((a,b);", "(a,b));", "(a[&x=1,b);", "('a,b);", "(a[&x=\"1],b);", "(a[&=1],b);", "(a:1é,b);"] {
+            assert!(parse(text).is_err(), "{text:?} parsed");
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! NeXML reader and writer (TreeBASE, Dryad deposits).
+//!
+//! Every `<tree>` in every `<trees>` block becomes a `Tree`; networks are
+//! ignored. Tips are named by their OTU's label (or id), and `<meta>`
+//! annotations on the OTU, the node and the edge leading to it are all merged
+//! into the node's annotations, with the property's namespace prefix removed.
+//! Literal meta values are typed by their `datatype`; untyped ones are read
+//! like NEXUS annotation values, so `{a,b}` becomes an array.
+//!
+//! The writer emits one OTUs block and one `FloatTree`, with each exported
+//! annotation as a `LiteralMeta` in the `peartree:` namespace. Keys are
+//! percent-encoded where they hold characters a CURIE cannot.
+
+use crate::newick::{parse_annotation_value, ParseError};
+use crate::phyloxml::{xml_error, xml_head, xsd_datatype, xsd_value};
+use crate::tree::Tree;
+use crate::writer::{children_of, display_label, write_value, xml_escape, WriteOptions};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+type XmlNode<'a, 'i> = roxmltree::Node<'a, 'i>;
+
+/// Returns true when `text` looks like a NeXML document.
+pub fn is_nexml(text: &str) -> bool {
+    let head = xml_head(text);
+    head.trim_start_matches('\u{feff}').trim_start().starts_with('<')
+        && (head.contains("<nexml") || head.contains("www.nexml.org/2009"))
+}
+
+/// Reads every tree in the document.
+pub fn parse_nexml(text: &str) -> Result<Vec<Tree>, ParseError> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| xml_error(text, e))?;
+    let root = doc.root_element();
+    let otus: HashMap<&str, XmlNode> = root
+        .descendants()
+        .filter(|n| n.has_tag_name("otu"))
+        .filter_map(|n| Some((n.attribute("id")?, n)))
+        .collect();
+    root.descendants()
+        .filter(|n| n.has_tag_name("tree"))
+        .enumerate()
+        .map(|(k, el)| read_tree(el, &otus).map_err(|m| ParseError::new(el.range().start, format!("tree {}: {m}", k + 1))))
+        .collect()
+}
+
+/// Builds the node table of one `<tree>` from its node and edge lists.
+fn read_tree(el: XmlNode, otus: &HashMap<&str, XmlNode>) -> Result<Tree, String> {
+    let nodes: Vec<XmlNode> = el.children().filter(|n| n.has_tag_name("node")).collect();
+    let index: HashMap<&str, usize> =
+        nodes.iter().enumerate().filter_map(|(i, n)| Some((n.attribute("id")?, i))).collect();
+    let mut children = vec![Vec::new(); nodes.len()];
+    let mut incoming: Vec<Option<XmlNode>> = vec![None; nodes.len()];
+    for edge in el.children().filter(|n| n.has_tag_name("edge")) {
+        let end = |attr| edge.attribute(attr).and_then(|id| index.get(id).copied());
+        let (Some(source), Some(target)) = (end("source"), end("target")) else {
+            return Err(format!("edge '{}' joins unknown nodes", edge.attribute("id").unwrap_or_default()));
+        };
+        if incoming[target].replace(edge).is_some() {
+            return Err(format!("node '{}' has more than one parent", nodes[target].attribute("id").unwrap_or_default()));
+        }
+        children[source].push(target);
+    }
+    let marked = nodes.iter().position(|n| n.attribute("root") == Some("true"));
+    let root = marked
+        .or_else(|| (0..nodes.len()).find(|&i| incoming[i].is_none()))
+        .ok_or("no root node")?;
+
+    let mut tree = Tree {
+        name: el.attribute("label").map(str::to_string),
+        rooted: marked.map(|_| true),
+        ..Default::default()
+    };
+    let root_edge = el
+        .children()
+        .find(|n| n.has_tag_name("rootedge") && n.attribute("target").and_then(|t| index.get(t)) == Some(&root));
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = vec![(root, None)];
+    while let Some((i, parent)) = stack.pop() {
+        if std::mem::replace(&mut visited[i], true) {
+            return Err("edges form a cycle".to_string());
+        }
+        let el = nodes[i];
+        let edge = if i == root { root_edge } else { incoming[i] };
+        let otu = el.attribute("otu").and_then(|id| otus.get(id));
+        let row = tree.push_node(parent);
+        let node = &mut tree.nodes[row];
+        node.length = edge.and_then(|e| e.attribute("length")).and_then(|l| l.trim().parse().ok());
+        node.label = if children[i].is_empty() {
+            otu.and_then(|o| o.attribute("label"))
+                .or(el.attribute("label"))
+                .or(otu.and_then(|o| o.attribute("id")))
+        } else {
+            el.attribute("label")
+        }
+        .map(str::to_string);
+        for holder in [otu.copied(), Some(el), edge].into_iter().flatten() {
+            read_meta(holder, &mut node.annotations);
+        }
+        for &c in children[i].iter().rev() {
+            stack.push((c, Some(row)));
+        }
+    }
+    Ok(tree)
+}
+
+/// Adds the `<meta>` children of `el` to `out`.
+fn read_meta(el: XmlNode, out: &mut Map<String, Value>) {
+    for meta in el.children().filter(|n| n.has_tag_name("meta")) {
+        let Some(key) = meta.attribute("property").or(meta.attribute("rel")) else { continue };
+        let key = percent_decode(key.split_once(':').map_or(key, |(_, k)| k));
+        let value = if let Some(href) = meta.attribute("href") {
+            Value::from(href)
+        } else {
+            let content = meta.attribute("content").or(meta.text()).map(str::trim).unwrap_or_default();
+            match meta.attribute("datatype") {
+                Some(datatype) => xsd_value(datatype, content),
+                None => parse_annotation_value(content),
+            }
+        };
+        out.insert(key, value);
+    }
+}
+
+/// Writes the subtree at `root` as a NeXML document with one tree.
+pub fn write_nexml(out: &mut impl Write, tree: &Tree, root: usize, opts: &WriteOptions) -> io::Result<()> {
+    let children = children_of(tree);
+    let mut rows = Vec::new();
+    let mut stack = vec![root];
+    while let Some(n) = stack.pop() {
+        rows.push(n);
+        stack.extend(children[n].iter().rev());
+    }
+    let label = |n: usize| display_label(tree, n, children[n].is_empty(), opts);
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<nex:nexml version="0.9" generator="PearTree" xmlns="http://www.nexml.org/2009" xmlns:nex="http://www.nexml.org/2009" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema#" xmlns:peartree="https://peartree.live/terms#">"#
+    )?;
+    writeln!(out, r#"  <otus id="otus1">"#)?;
+    for &n in rows.iter().filter(|&&n| children[n].is_empty()) {
+        match label(n) {
+            Some(name) => writeln!(out, r#"    <otu id="otu{n}" label="{}"/>"#, xml_escape(&name))?,
+            None => writeln!(out, r#"    <otu id="otu{n}"/>"#)?,
+        }
+    }
+    writeln!(out, "  </otus>")?;
+    writeln!(out, r#"  <trees id="trees1" otus="otus1">"#)?;
+    write!(out, r#"    <tree id="tree1" xsi:type="nex:FloatTree""#)?;
+    if let Some(name) = &tree.name {
+        write!(out, r#" label="{}""#, xml_escape(name))?;
+    }
+    writeln!(out, ">")?;
+
+    let mut meta_id = 0;
+    for &n in &rows {
+        let is_tip = children[n].is_empty();
+        write!(out, r#"      <node id="n{n}""#)?;
+        if is_tip {
+            write!(out, r#" otu="otu{n}""#)?;
+        } else if let Some(name) = label(n) {
+            write!(out, r#" label="{}""#, xml_escape(&name))?;
+        }
+        if n == root {
+            write!(out, r#" root="true""#)?;
+        }
+        let annots = &tree.nodes[n].annotations;
+        let metas: Vec<(&String, &Value)> = opts
+            .annotations
+            .iter()
+            .filter_map(|k| annots.get(k).filter(|v| !v.is_null()).map(|v| (k, v)))
+            .collect();
+        if metas.is_empty() {
+            writeln!(out, "/>")?;
+            continue;
+        }
+        writeln!(out, ">")?;
+        for (key, value) in metas {
+            meta_id += 1;
+            write!(out, r#"        <meta xsi:type="nex:LiteralMeta" id="m{meta_id}" property="peartree:{}""#, percent_encode(key))?;
+            let content = match value {
+                Value::String(s) => s.clone(),
+                Value::Array(_) => {
+                    let mut buf = Vec::new();
+                    write_value(&mut buf, value)?;
+                    String::from_utf8_lossy(&buf).into_owned()
+                }
+                other => other.to_string(),
+            };
+            write!(out, r#" content="{}""#, xml_escape(&content))?;
+            if let Some(datatype) = xsd_datatype(value) {
+                write!(out, r#" datatype="{datatype}""#)?;
+            }
+            writeln!(out, "/>")?;
+        }
+        writeln!(out, "      </node>")?;
+    }
+    for &n in &rows[1..] {
+        let parent = tree.nodes[n].parent.unwrap_or(root);
+        write!(out, r#"      <edge id="e{n}" source="n{parent}" target="n{n}""#)?;
+        if let Some(length) = tree.nodes[n].length {
+            write!(out, r#" length="{length}""#)?;
+        }
+        writeln!(out, "/>")?;
+    }
+    writeln!(out, "    </tree>")?;
+    writeln!(out, "  </trees>")?;
+    writeln!(out, "</nex:nexml>")
+}
+
+/// Escapes everything but letters, digits and `_ - .` as `%XX`.
+fn percent_encode(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn percent_decode(key: &str) -> String {
+    let bytes = key.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| key.get(i + 1..i + 3)).flatten();
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::Format;
+    use serde_json::json;
+
+    /// `((a,b)node,c)` with scalar, array and awkwardly named annotations.
+    fn sample() -> Tree {
+        let mut tree = Tree { name: Some("tree & co".into()), rooted: Some(true), ..Default::default() };
+        let root = tree.push_node(None);
+        let inner = tree.push_node(Some(root));
+        tree.nodes[inner].label = Some("node".into());
+        tree.nodes[inner].length = Some(0.5);
+        tree.nodes[inner].annotations.insert("posterior".into(), json!(0.95));
+        for (parent, label) in [(inner, "a"), (inner, "b \"1\""), (root, "c")] {
+            let n = tree.push_node(Some(parent));
+            let node = &mut tree.nodes[n];
+            node.label = Some(label.into());
+            node.length = Some(1.5);
+            node.annotations.insert("host <1>".into(), json!("bat & rat"));
+            node.annotations.insert("count".into(), json!(3));
+            node.annotations.insert("sampled".into(), json!(false));
+            node.annotations.insert("set".into(), json!(["p'q", "r\"s", 2]));
+        }
+        tree
+    }
+
+    #[test]
+    fn round_trips_names_lengths_and_annotations() {
+        let tree = sample();
+        let opts = WriteOptions {
+            format: Format::NeXml,
+            annotations: ["posterior", "host <1>", "count", "sampled", "set"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_nexml(&mut out, &tree, 0, &opts).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(is_nexml(&text));
+        let back = parse_nexml(&text).unwrap().remove(0);
+        assert_eq!((&back.name, back.rooted), (&tree.name, tree.rooted));
+        assert_eq!(back.nodes.len(), tree.nodes.len());
+        for (a, b) in tree.nodes.iter().zip(&back.nodes) {
+            assert_eq!((a.parent, a.length, &a.label, &a.annotations), (b.parent, b.length, &b.label, &b.annotations));
+        }
+    }
+
+    #[test]
+    fn finds_the_root_without_a_marker() {
+        let text = r#"<nexml xmlns="http://www.nexml.org/2009"><otus id="o"><otu id="t1" label="a"/><otu id="t2"/></otus>
+            <trees otus="o"><tree id="t"><node id="x" otu="t1"/><node id="r"/><node id="y" otu="t2"/>
+            <edge id="e1" source="r" target="x" length="2"/><edge id="e2" source="r" target="y"/></tree></trees></nexml>"#;
+        let tree = parse_nexml(text).unwrap().remove(0);
+        let labels: Vec<_> = tree.nodes.iter().map(|n| n.label.as_deref()).collect();
+        assert_eq!(labels, [None, Some("a"), Some("t2")]);
+        assert_eq!(tree.nodes[1].length, Some(2.0));
+        assert_eq!(tree.rooted, None);
+    }
+
+    #[test]
+    fn sniffs_multibyte_text_safely() {
+        assert!(is_nexml(&format!("<nexml>{}", "é".repeat(3000))));
+        assert!(!is_nexml(&format!("<x>{}", "é".repeat(3000))));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let tree = |body: &str| format!(r#"<nexml><trees><tree id="t">{body}</tree></trees></nexml>"#);
+        for text in [
+            "<nexml><trees>".to_string(),
+            tree(r#"<node id="a"/><edge id="e" source="a" target="b"/>"#),
+            tree(r#"<node id="a"/><node id="b"/><node id="c"/><edge id="e1" source="a" target="c"/><edge id="e2" source="b" target="c"/>"#),
+            tree(r#"<node id="a"/><node id="b"/><edge id="e1" source="a" target="b"/><edge id="e2" source="b" target="a"/>"#),
+        ] {
+            assert!(parse_nexml(&text).is_err(), "{text:?} parsed");
+        }
+    }
+}
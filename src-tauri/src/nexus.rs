@@ -0,0 +1,427 @@
+//! Native NEXUS reader.
+//!
+//! Understands the parts of NEXUS that matter to a tree viewer: `TAXA` blocks
+//! (with `[&...]` taxon annotations after each TAXLABELS entry), `TREES`
+//! blocks with an optional `TRANSLATE` table, and the `[peartree={...}]`
+//! settings comment written by PearTree's own NEXUS export. Block and command
+//! names are case-insensitive; every other block is skipped command by command.
+//!
+//! FigTree's `[&!color=...]` node colours and `FIGTREE` block options are
+//! translated to PearTree's user colours and settings (see `figtree.rs`).
+//!
+//! MrBayes `sumt` annotations (`[&prob=...,prob_stddev=...,length_95%HPD={...}]`)
+//! are renamed to BEAST's conventions so their HPD intervals group the same way.
+//!
+//! Files are indexed rather than parsed up front: one pass records where each
+//! tree statement starts, and trees are then parsed on demand, so a BEAST
+//! posterior sample with thousands of trees opens as quickly as a single tree.
+
+use crate::figtree;
+use crate::newick::{self, ParseError};
+use crate::tree::{LoadWarning, Tree};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Returns true when `text` starts with the `#NEXUS` signature.
+pub fn is_nexus(text: &str) -> bool {
+    let head = text.trim_start_matches('\u{feff}').trim_start();
+    head.as_bytes().get(..6).is_some_and(|h| h.eq_ignore_ascii_case(b"#nexus"))
+}
+
+/// Where one tree statement sits in the file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TreeEntry {
+    pub name: Option<String>,
+    /// Byte offset of the tree's Newick text (just after the `=`).
+    pub offset: usize,
+    /// Which TREES block's TRANSLATE table applies.
+    #[serde(skip)]
+    pub(crate) translate: usize,
+}
+
+/// Result of a single pass over a NEXUS file: the position of every tree
+/// plus the TRANSLATE tables, TAXA annotations and settings needed to read
+/// any one of them later without rescanning the file.
+pub struct NexusIndex {
+    pub trees: Vec<TreeEntry>,
+    pub settings: Option<Value>,
+    translates: Vec<HashMap<String, String>>,
+    taxa: Taxa,
+}
+
+/// Scans a NEXUS file, locating tree statements without parsing them.
+pub fn index_nexus(text: &str) -> Result<NexusIndex, ParseError> {
+    if !is_nexus(text) {
+        return Err(ParseError::new(0, "missing #NEXUS header"));
+    }
+    let start = text.find('#').unwrap_or(0) + 6;
+    let mut sc = Scanner { text, pos: start, settings: None };
+    let mut taxa = Taxa::default();
+    let mut trees = Vec::new();
+    let mut translates = Vec::new();
+    let mut figtree_options = HashMap::new();
+
+    while let Some(word) = sc.word()? {
+        if !word.eq_ignore_ascii_case("begin") {
+            sc.skip_command()?;
+            continue;
+        }
+        let block = sc.word()?.unwrap_or_default().to_ascii_lowercase();
+        sc.expect(b';')?;
+        if block == "trees" {
+            translates.push(HashMap::new());
+        }
+        loop {
+            let Some(cmd) = sc.word()? else {
+                return Err(ParseError::new(sc.pos, format!("unterminated {block} block")));
+            };
+            let cmd = cmd.to_ascii_lowercase();
+            match (block.as_str(), cmd.as_str()) {
+                (_, "end" | "endblock") => {
+                    sc.expect(b';')?;
+                    break;
+                }
+                ("taxa", "taxlabels") => taxa.read_labels(&mut sc)?,
+                ("figtree", "set") => sc.read_set(&mut figtree_options)?,
+                ("trees", "translate") => {
+                    let table = sc.read_translate()?;
+                    if let Some(last) = translates.last_mut() {
+                        *last = table;
+                    }
+                }
+                ("trees", "tree" | "utree") => {
+                    let mut name = sc.word()?;
+                    if name.as_deref() == Some("*") {
+                        name = sc.word()?;
+                    }
+                    sc.expect(b'=')?;
+                    let offset = sc.pos;
+                    let mut parser = newick::Parser::new(text, offset);
+                    parser.skip_tree()?;
+                    sc.pos = parser.pos;
+                    trees.push(TreeEntry { name, offset, translate: translates.len() - 1 });
+                }
+                _ => sc.skip_command()?,
+            }
+        }
+    }
+    // Settings PearTree wrote itself win over those translated from FigTree.
+    let mut settings = figtree::settings(&figtree_options);
+    let settings = match sc.settings {
+        Some(Value::Object(own)) => {
+            settings.extend(own);
+            Some(Value::Object(settings))
+        }
+        Some(other) => Some(other),
+        None => (!settings.is_empty()).then_some(Value::Object(settings)),
+    };
+    Ok(NexusIndex { trees, settings, translates, taxa })
+}
+
+impl NexusIndex {
+    /// Parses tree `i` of the indexed `text`, applying its TRANSLATE table and
+    /// merging TAXA annotations onto tips. Tips missing from an annotated TAXA
+    /// block come back as a `TaxaBlockMismatch` warning.
+    pub fn read_tree(&self, text: &str, i: usize) -> Result<(Tree, Option<LoadWarning>), ParseError> {
+        let entry = &self.trees[i];
+        let mut parser = newick::Parser::new(text, entry.offset);
+        let mut tree = parser.parse_tree()?;
+        tree.name = entry.name.clone();
+        let translate = &self.translates[entry.translate];
+        if !translate.is_empty() {
+            for t in tree.tips() {
+                let node = &mut tree.nodes[t];
+                if let Some(label) = node.label.as_ref().and_then(|l| translate.get(l)) {
+                    node.label = Some(label.clone());
+                }
+            }
+        }
+        let mut warning = None;
+        if self.taxa.annotated {
+            let names = self.taxa.apply(&mut tree);
+            if !names.is_empty() {
+                warning = Some(LoadWarning::TaxaBlockMismatch { tree: i, count: names.len(), names });
+            }
+        }
+        for node in &mut tree.nodes {
+            mrbayes_annotations(&mut node.annotations);
+            figtree::user_colour(&mut node.annotations);
+        }
+        Ok((tree, warning))
+    }
+}
+
+/// Rewrites MrBayes annotation names and display strings to match BEAST's:
+/// `length_95%HPD` becomes `length_95%_HPD`, and `prob(percent)="98"` becomes
+/// the number `prob_percent`. The `prob+-sd="98+-1"` string only repeats
+/// `prob` and `prob_stddev`, so it is dropped.
+fn mrbayes_annotations(annotations: &mut Map<String, Value>) {
+    if let Some(percent) = annotations.remove("prob(percent)") {
+        let percent = match percent {
+            Value::String(s) => newick::typed_value(s.trim()),
+            other => other,
+        };
+        annotations.insert("prob_percent".into(), percent);
+    }
+    annotations.remove("prob+-sd");
+    let hpds: Vec<String> = annotations.keys().filter(|k| k.ends_with("_95%HPD")).cloned().collect();
+    for key in hpds {
+        if let Some(value) = annotations.remove(&key) {
+            annotations.insert(key.replace("_95%HPD", "_95%_HPD"), value);
+        }
+    }
+}
+
+/// TAXLABELS entries keyed by taxon name, with any annotations attached to them.
+#[derive(Default)]
+struct Taxa {
+    annotations: HashMap<String, Map<String, Value>>,
+    annotated: bool,
+}
+
+impl Taxa {
+    fn read_labels(&mut self, sc: &mut Scanner) -> Result<(), ParseError> {
+        loop {
+            let mut annots = Map::new();
+            sc.skip_ws_and_comments(&mut annots)?;
+            if sc.peek() == Some(b';') {
+                sc.pos += 1;
+                return Ok(());
+            }
+            let label = sc
+                .token()?
+                .ok_or_else(|| ParseError::new(sc.pos, "unterminated TAXLABELS command"))?;
+            sc.skip_ws_and_comments(&mut annots)?;
+            self.annotated |= !annots.is_empty();
+            self.annotations.insert(label, annots);
+        }
+    }
+
+    /// Merges taxon annotations onto tips (annotations already on a tip win)
+    /// and returns the names of tips missing from the block.
+    fn apply(&self, tree: &mut Tree) -> Vec<String> {
+        let mut missing = Vec::new();
+        for i in tree.tips() {
+            let node = &mut tree.nodes[i];
+            let name = node.label.clone().unwrap_or_default();
+            match self.annotations.get(&name) {
+                Some(annots) => {
+                    for (k, v) in annots {
+                        node.annotations.entry(k.clone()).or_insert_with(|| v.clone());
+                    }
+                }
+                None => missing.push(name),
+            }
+        }
+        missing
+    }
+}
+
+/// Command-level cursor over NEXUS text.
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Set when a `[peartree={...}]` comment is seen between commands.
+    settings: Option<Value>,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skips whitespace and comments, merging `[&...]` annotations into
+    /// `annots` and capturing a PearTree settings comment if one goes by.
+    fn skip_ws_and_comments(&mut self, annots: &mut Map<String, Value>) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(b'[') => {
+                    let start = self.pos;
+                    let end = newick::comment_end(self.text, start)
+                        .ok_or_else(|| ParseError::new(start, "unterminated comment"))?;
+                    let body = &self.text[start + 1..end];
+                    if let Some(annot) = body.strip_prefix('&') {
+                        newick::parse_annotations(annot, annots)
+                            .map_err(|e| ParseError::new(start + 2 + e.offset, e.message))?;
+                    } else if let Some(json) = body.trim_start().strip_prefix("peartree=") {
+                        // Malformed settings are ignored rather than failing the load.
+                        self.settings = serde_json::from_str(json).ok().or(self.settings.take());
+                    }
+                    self.pos = end + 1;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+        self.skip_ws_and_comments(&mut Map::new())?;
+        if self.peek() != Some(c) {
+            return Err(ParseError::new(self.pos, format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Reads the next word (command or block name), skipping comments.
+    fn word(&mut self) -> Result<Option<String>, ParseError> {
+        self.skip_ws_and_comments(&mut Map::new())?;
+        self.token()
+    }
+
+    /// Reads one quoted or unquoted NEXUS token at the cursor.
+    fn token(&mut self) -> Result<Option<String>, ParseError> {
+        match self.peek() {
+            None => Ok(None),
+            Some(q @ (b'\'' | b'"')) => {
+                let mut p = newick::Parser::new(self.text, self.pos);
+                let s = p.parse_quoted(q)?;
+                self.pos = p.pos;
+                Ok(Some(s))
+            }
+            Some(_) => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_whitespace() || matches!(c, b';' | b',' | b'=' | b'[' | b']') {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    // A lone punctuation character is a token of its own.
+                    self.pos += 1;
+                }
+                Ok(Some(self.text[start..self.pos].to_string()))
+            }
+        }
+    }
+
+    /// Advances past the `;` ending the current command, honouring quotes and
+    /// comments (which may themselves contain `;`).
+    fn skip_command(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_ws_and_comments(&mut Map::new())?;
+            match self.peek() {
+                None => return Ok(()),
+                Some(b';') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(_) => {
+                    self.token()?;
+                }
+            }
+        }
+    }
+
+    /// Reads FigTree's `SET key=value;` into `options`. Anything else is
+    /// skipped as a whole command.
+    fn read_set(&mut self, options: &mut HashMap<String, String>) -> Result<(), ParseError> {
+        let Some(key) = self.word()? else { return Ok(()) };
+        if key == ";" {
+            return Ok(());
+        }
+        self.skip_ws_and_comments(&mut Map::new())?;
+        if self.peek() == Some(b'=') {
+            self.pos += 1;
+            match self.word()? {
+                None => return Ok(()),
+                Some(value) if value == ";" => return Ok(()),
+                Some(value) => {
+                    options.insert(key, value);
+                }
+            }
+        }
+        self.skip_command()
+    }
+
+    /// Reads `TRANSLATE key label, key label, ...;`.
+    fn read_translate(&mut self) -> Result<HashMap<String, String>, ParseError> {
+        let mut table = HashMap::new();
+        loop {
+            let Some(key) = self.word()? else {
+                return Err(ParseError::new(self.pos, "unterminated TRANSLATE command"));
+            };
+            if key == ";" {
+                return Ok(table);
+            }
+            let label = self
+                .word()?
+                .ok_or_else(|| ParseError::new(self.pos, "unterminated TRANSLATE command"))?;
+            table.insert(key, label);
+            match self.word()?.as_deref() {
+                Some(",") => {}
+                Some(";") => return Ok(table),
+                _ => return Err(ParseError::new(self.pos, "expected ',' or ';' in TRANSLATE")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BEAST: &str = "#NEXUS\n\
+        Begin taxa;\n\tDimensions ntax=3;\n\tTaxlabels\n\t\ta[&host=\"bat\"]\n\t\tb\n\t\tc\n\t\t;\nEnd;\n\
+        Begin trees;\n\tTranslate\n\t\t1 a,\n\t\t2 b,\n\t\t3 'c c'\n\t\t;\n\
+        tree STATE_0 = [&R] ((1:1,2:1)[&prob(percent)=\"98\",length_95%HPD={0.5,1.5}]:1,3:2);\n\
+        tree STATE_1 = [&R] ((1:1,3:1):1,2:2);\nEnd;\n\
+        [peartree={\"tipColourBy\":\"host\"}]\n";
+
+    fn read(text: &str, i: usize) -> (Tree, Option<LoadWarning>) {
+        index_nexus(text).unwrap().read_tree(text, i).unwrap()
+    }
+
+    #[test]
+    fn indexes_trees_and_settings() {
+        let index = index_nexus(BEAST).unwrap();
+        let names: Vec<_> = index.trees.iter().map(|t| t.name.as_deref()).collect();
+        assert_eq!(names, [Some("STATE_0"), Some("STATE_1")]);
+        assert_eq!(index.settings, Some(json!({ "tipColourBy": "host" })));
+    }
+
+    #[test]
+    fn translates_tips_and_merges_taxa_annotations() {
+        let (tree, warning) = read(BEAST, 1);
+        let tips: Vec<_> = tree.tips().iter().map(|&t| tree.nodes[t].label.clone().unwrap()).collect();
+        assert_eq!(tips, ["a", "c c", "b"]);
+        assert_eq!(tree.nodes[2].annotations["host"], json!("bat"));
+        // 'c c' has no TAXLABELS entry.
+        assert!(matches!(warning, Some(LoadWarning::TaxaBlockMismatch { .. })));
+    }
+
+    #[test]
+    fn renames_mrbayes_annotations() {
+        let (tree, _) = read(BEAST, 0);
+        let annots = &tree.nodes[1].annotations;
+        assert_eq!(annots["prob_percent"], json!(98));
+        assert_eq!(annots["length_95%_HPD"], json!([0.5, 1.5]));
+    }
+
+    #[test]
+    fn recognises_the_header() {
+        assert!(is_nexus("\u{feff}  #nexus\n"));
+        assert!(!is_nexus("#NEX"));
+        assert!(!is_nexus("#NEXé"));
+        assert!(!is_nexus("(a,b);"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [
+            "(a,b);",
+            "#NEXUS\nbegin trees;\ntree t = (a,b);\n",
+            "#NEXUS\nbegin trees;\ntree t (a,b);\nend;\n",
+            "#NEXUS\nbegin taxa;\ntaxlabels a b\n",
+        ] {
+            assert!(index_nexus(text).is_err(), "{text:?} indexed");
+        }
+        // Trees are only checked when read.
+        let text = "#NEXUS\nbegin trees;\ntree t = ((a,b);\nend;\n";
+        assert!(index_nexus(text).unwrap().read_tree(text, 0).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! phyloXML reader and writer (Archaeopteryx, ETE, Forester).
+//!
+//! Each `<phylogeny>` becomes a `Tree`. Clade elements map to node fields and
+//! annotations as follows, and the writer maps them back the same way:
+//!
+//! | phyloXML                              | PearTree                          |
+//! |---------------------------------------|-----------------------------------|
+//! | `<name>`                              | label                             |
+//! | `branch_length` (attribute or child)  | branch length                     |
+//! | `<confidence type="T">`               | annotation `T` (`confidence` if untyped) |
+//! | `<taxonomy>` `id`, `code`             | `taxonomy_id`, `taxonomy_code`    |
+//! | `<taxonomy>` `scientific_name`, `common_name`, `rank` | same names        |
+//! | `<color>`                             | `user_colour` as `#rrggbb`        |
+//! | `<property ref="prefix:K">`           | annotation `K`, typed by `datatype` |
+//!
+//! A tip without a `<name>` takes its taxonomy's scientific name or code.
+
+use crate::newick::{typed_value, ParseError};
+use crate::tree::Tree;
+use crate::writer::{children_of, display_label, xml_escape, WriteOptions};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// Confidence types written back as `<confidence>` rather than `<property>`.
+const CONFIDENCE_TYPES: &[&str] = &["bootstrap", "posterior", "probability", "support", "confidence"];
+
+/// Taxonomy child elements and the annotation each one is stored under.
+const TAXONOMY_FIELDS: &[(&str, &str)] = &[
+    ("id", "taxonomy_id"),
+    ("code", "taxonomy_code"),
+    ("scientific_name", "scientific_name"),
+    ("common_name", "common_name"),
+    ("rank", "rank"),
+];
+
+/// Returns true when `text` looks like a phyloXML document.
+pub fn is_phyloxml(text: &str) -> bool {
+    let head = xml_head(text);
+    head.trim_start_matches('\u{feff}').trim_start().starts_with('<') && head.contains("<phyloxml")
+}
+
+/// The first 4 KB or so of `text`, cut at a character boundary, where the
+/// XML sniffers look for the root element.
+pub(crate) fn xml_head(text: &str) -> &str {
+    let mut end = text.len().min(4096);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Converts an XML error to a `ParseError` located by byte offset.
+pub(crate) fn xml_error(text: &str, err: roxmltree::Error) -> ParseError {
+    let pos = err.pos();
+    ParseError::at_line(text, pos.row as usize, pos.col as usize, err.to_string())
+}
+
+/// Reads every `<phylogeny>` in the document.
+pub fn parse_phyloxml(text: &str) -> Result<Vec<Tree>, ParseError> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| xml_error(text, e))?;
+    let mut trees = Vec::new();
+    for phylogeny in doc.root_element().children().filter(|n| n.has_tag_name("phylogeny")) {
+        let mut tree = Tree {
+            name: child_text(phylogeny, "name"),
+            rooted: phylogeny.attribute("rooted").map(|r| r == "true"),
+            ..Default::default()
+        };
+        let mut stack: Vec<(roxmltree::Node, Option<usize>)> =
+            phylogeny.children().filter(|n| n.has_tag_name("clade")).map(|c| (c, None)).collect();
+        stack.reverse();
+        while let Some((clade, parent)) = stack.pop() {
+            let row = tree.push_node(parent);
+            read_clade(clade, &mut tree, row);
+            let children: Vec<_> = clade.children().filter(|n| n.has_tag_name("clade")).collect();
+            for child in children.into_iter().rev() {
+                stack.push((child, Some(row)));
+            }
+        }
+        if !tree.nodes.is_empty() {
+            trees.push(tree);
+        }
+    }
+    Ok(trees)
+}
+
+/// Fills in node `row` from the non-clade children of a `<clade>` element.
+fn read_clade(clade: roxmltree::Node, tree: &mut Tree, row: usize) {
+    let node = &mut tree.nodes[row];
+    let annots = &mut node.annotations;
+    node.length = clade.attribute("branch_length").and_then(|v| v.trim().parse().ok());
+    let mut is_tip = true;
+    for el in clade.children().filter(|n| n.is_element()) {
+        let text = el.text().map(str::trim).unwrap_or_default();
+        match el.tag_name().name() {
+            "clade" => is_tip = false,
+            "name" if !text.is_empty() => node.label = Some(text.to_string()),
+            "branch_length" => node.length = text.parse().ok().or(node.length),
+            "confidence" => {
+                let key = el.attribute("type").filter(|t| !t.is_empty()).unwrap_or("confidence");
+                annots.insert(key.to_string(), typed_value(text));
+            }
+            "taxonomy" => {
+                for &(tag, key) in TAXONOMY_FIELDS {
+                    if let Some(v) = child_text(el, tag) {
+                        annots.insert(key.to_string(), Value::from(v));
+                    }
+                }
+            }
+            "color" => {
+                let channel = |tag| child_text(el, tag).and_then(|v| v.parse::<u8>().ok()).unwrap_or(0);
+                let hex = format!("#{:02x}{:02x}{:02x}", channel("red"), channel("green"), channel("blue"));
+                annots.insert("user_colour".into(), Value::from(hex));
+            }
+            "property" => {
+                let Some(r) = el.attribute("ref") else { continue };
+                let key = r.split_once(':').map_or(r, |(_, k)| k);
+                annots.insert(key.to_string(), xsd_value(el.attribute("datatype").unwrap_or("xsd:string"), text));
+            }
+            _ => {}
+        }
+    }
+    if is_tip && node.label.is_none() {
+        node.label = ["scientific_name", "taxonomy_code"]
+            .iter()
+            .find_map(|k| annots.get(*k).and_then(Value::as_str).map(str::to_string));
+    }
+}
+
+fn child_text(el: roxmltree::Node, tag: &str) -> Option<String> {
+    el.children()
+        .find(|n| n.has_tag_name(tag))
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+enum Step {
+    Open(usize, usize),
+    Close(usize),
+}
+
+/// Writes the subtree at `root` as a phyloXML document with one phylogeny.
+pub fn write_phyloxml(out: &mut impl Write, tree: &Tree, root: usize, opts: &WriteOptions) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<phyloxml xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.phyloxml.org http://www.phyloxml.org/1.20/phyloxml.xsd" xmlns="http://www.phyloxml.org">"#
+    )?;
+    writeln!(out, r#"  <phylogeny rooted="true">"#)?;
+    if let Some(name) = &tree.name {
+        writeln!(out, "    <name>{}</name>", xml_escape(name))?;
+    }
+    let children = children_of(tree);
+    let mut stack = vec![Step::Open(root, 2)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Open(n, depth) => {
+                let indent = "  ".repeat(depth.min(32));
+                writeln!(out, "{indent}<clade>")?;
+                write_clade_fields(out, tree, n, n == root, children[n].is_empty(), opts, &indent)?;
+                stack.push(Step::Close(depth));
+                for &c in children[n].iter().rev() {
+                    stack.push(Step::Open(c, depth + 1));
+                }
+            }
+            Step::Close(depth) => writeln!(out, "{}</clade>", "  ".repeat(depth.min(32)))?,
+        }
+    }
+    writeln!(out, "  </phylogeny>")?;
+    writeln!(out, "</phyloxml>")
+}
+
+/// Writes a clade's own elements, in the order the phyloXML schema requires.
+fn write_clade_fields(
+    out: &mut impl Write,
+    tree: &Tree,
+    n: usize,
+    is_root: bool,
+    is_tip: bool,
+    opts: &WriteOptions,
+    indent: &str,
+) -> io::Result<()> {
+    let node = &tree.nodes[n];
+    let selected: Map<String, Value> = opts
+        .annotations
+        .iter()
+        .filter_map(|k| node.annotations.get(k).filter(|v| !v.is_null()).map(|v| (k.clone(), v.clone())))
+        .collect();
+    if let Some(label) = display_label(tree, n, is_tip, opts) {
+        writeln!(out, "{indent}  <name>{}</name>", xml_escape(&label))?;
+    }
+    if let (Some(length), false) = (node.length, is_root) {
+        writeln!(out, "{indent}  <branch_length>{length}</branch_length>")?;
+    }
+    for (key, value) in &selected {
+        if CONFIDENCE_TYPES.contains(&key.as_str()) && value.is_number() {
+            writeln!(out, r#"{indent}  <confidence type="{}">{value}</confidence>"#, xml_escape(key))?;
+        }
+    }
+    if let Some(rgb) = selected.get("user_colour").and_then(Value::as_str).and_then(parse_hex_colour) {
+        writeln!(out, "{indent}  <color>")?;
+        writeln!(out, "{indent}    <red>{}</red>\n{indent}    <green>{}</green>\n{indent}    <blue>{}</blue>", rgb[0], rgb[1], rgb[2])?;
+        writeln!(out, "{indent}  </color>")?;
+    }
+    let taxonomy: Vec<(&str, &Value)> =
+        TAXONOMY_FIELDS.iter().filter_map(|&(tag, key)| selected.get(key).map(|v| (tag, v))).collect();
+    if !taxonomy.is_empty() {
+        writeln!(out, "{indent}  <taxonomy>")?;
+        for (tag, value) in taxonomy {
+            writeln!(out, "{indent}    <{tag}>{}</{tag}>", xml_escape(&plain_text(value)))?;
+        }
+        writeln!(out, "{indent}  </taxonomy>")?;
+    }
+    for (key, value) in &selected {
+        let mapped = (CONFIDENCE_TYPES.contains(&key.as_str()) && value.is_number())
+            || (key == "user_colour" && value.as_str().and_then(parse_hex_colour).is_some())
+            || TAXONOMY_FIELDS.iter().any(|&(_, k)| k == key);
+        if mapped {
+            continue;
+        }
+        let datatype = xsd_datatype(value).unwrap_or("xsd:string");
+        writeln!(
+            out,
+            r#"{indent}  <property ref="peartree:{}" datatype="{datatype}" applies_to="clade">{}</property>"#,
+            xml_escape(key),
+            xml_escape(&plain_text(value)),
+        )?;
+    }
+    Ok(())
+}
+
+/// Types element or attribute text by its XML Schema `datatype`.
+pub(crate) fn xsd_value(datatype: &str, text: &str) -> Value {
+    match datatype.rsplit(':').next().unwrap_or_default() {
+        "boolean" => Value::Bool(text == "true" || text == "1"),
+        "double" | "float" | "decimal" | "integer" | "int" | "long" | "short" | "byte"
+        | "nonNegativeInteger" | "positiveInteger" | "nonPositiveInteger" | "negativeInteger"
+        | "unsignedLong" | "unsignedInt" | "unsignedShort" | "unsignedByte" => typed_value(text),
+        _ => Value::from(text),
+    }
+}
+
+/// The XML Schema datatype for a scalar annotation value; `None` for arrays.
+pub(crate) fn xsd_datatype(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Number(x) if x.is_i64() || x.is_u64() => Some("xsd:integer"),
+        Value::Number(_) => Some("xsd:double"),
+        Value::Bool(_) => Some("xsd:boolean"),
+        Value::Array(_) => None,
+        _ => Some("xsd:string"),
+    }
+}
+
+/// An annotation value as element text: strings bare, arrays comma-separated.
+pub(crate) fn plain_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(plain_text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Parses `#rrggbb` (or `#rgb`) into its channels.
+pub(crate) fn parse_hex_colour(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#')?;
+    let expanded: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(expanded.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::Format;
+    use serde_json::json;
+
+    /// `((a,b)90,c)` with one annotation of each kind the format maps.
+    fn sample() -> Tree {
+        let mut tree = Tree { name: Some("flu & co".into()), ..Default::default() };
+        let root = tree.push_node(None);
+        let inner = tree.push_node(Some(root));
+        tree.nodes[inner].length = Some(0.5);
+        tree.nodes[inner].annotations.insert("bootstrap".into(), json!(90));
+        for (parent, label) in [(inner, "a"), (inner, "b <1>"), (root, "c")] {
+            let n = tree.push_node(Some(parent));
+            let node = &mut tree.nodes[n];
+            node.label = Some(label.into());
+            node.length = Some(1.5);
+            node.annotations.insert("scientific_name".into(), json!("Homo sapiens"));
+            node.annotations.insert("user_colour".into(), json!("#ff8000"));
+            node.annotations.insert("host".into(), json!("bat \"x\""));
+            node.annotations.insert("count".into(), json!(3));
+            node.annotations.insert("rate".into(), json!(0.25));
+            node.annotations.insert("sampled".into(), json!(true));
+        }
+        tree
+    }
+
+    fn round_trip(tree: &Tree, annotations: &[&str]) -> Tree {
+        let opts = WriteOptions {
+            format: Format::PhyloXml,
+            annotations: annotations.iter().map(|k| k.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_phyloxml(&mut out, tree, 0, &opts).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(is_phyloxml(&text));
+        let mut trees = parse_phyloxml(&text).unwrap();
+        assert_eq!(trees.len(), 1);
+        trees.remove(0)
+    }
+
+    #[test]
+    fn round_trips_names_lengths_and_annotations() {
+        let tree = sample();
+        let keys = ["bootstrap", "scientific_name", "user_colour", "host", "count", "rate", "sampled"];
+        let back = round_trip(&tree, &keys);
+        assert_eq!(back.name, tree.name);
+        assert_eq!(back.rooted, Some(true));
+        assert_eq!(back.nodes.len(), tree.nodes.len());
+        for (a, b) in tree.nodes.iter().zip(&back.nodes) {
+            assert_eq!((a.parent, &a.label, &a.annotations), (b.parent, &b.label, &b.annotations));
+        }
+        assert_eq!(back.nodes[1].length, Some(0.5));
+        assert_eq!(back.nodes[2].length, Some(1.5));
+    }
+
+    #[test]
+    fn names_tips_from_their_taxonomy() {
+        let text = r#"<phyloxml><phylogeny><clade><clade branch_length="2"><taxonomy><code>HUMAN</code></taxonomy></clade><clade><name>b</name></clade></clade></phylogeny></phyloxml>"#;
+        let tree = parse_phyloxml(text).unwrap().remove(0);
+        assert_eq!(tree.nodes[1].label.as_deref(), Some("HUMAN"));
+        assert_eq!(tree.nodes[1].length, Some(2.0));
+    }
+
+    #[test]
+    fn sniffs_multibyte_text_safely() {
+        assert!(is_phyloxml(&format!("<phyloxml>{}", "é".repeat(3000))));
+        assert!(!is_phyloxml(&format!("<x>{}", "é".repeat(3000))));
+        assert!(!is_phyloxml("(a,b);"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in ["<phyloxml><phylogeny><clade>", "<phyloxml><phylogeny></clade></phylogeny></phyloxml>", "<phyloxml a=1/>"] {
+            assert!(parse_phyloxml(text).is_err(), "{text:?} parsed");
+        }
+    }
+}
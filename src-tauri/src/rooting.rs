@@ -0,0 +1,414 @@
+//! Rerooting, midpoint rooting and temporal rooting of a node table.
+//!
+//! The frontend flattens its tree graph into a node table (see `tree.rs`);
+//! this module finds the new root and returns the table rooted there, along
+//! with the input row each output row came from, so the frontend can re-root
+//! its graph in place without rebuilding it. A bifurcating root is a virtual
+//! node on an edge: it is dropped and its two edges joined before rerooting,
+//! as `fromNestedRoot` does.
+//!
+//! Temporal rooting minimises the residual of the root-to-tip regression
+//! against tip dates, or the variance of root-to-tip distances when there
+//! are no dates. Every edge is solved analytically from per-subtree sums, so
+//! a global search is a single O(n) pass.
+
+use crate::tree::{Tree, TreeNode};
+use serde::{Deserialize, Serialize};
+
+/// Where to put the root.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RootMethod {
+    /// On the branch above row `node`, `distance` from its parent end.
+    Branch { node: usize, distance: f64 },
+    /// Halfway along the longest tip-to-tip path.
+    Midpoint,
+    /// At the best root-to-tip fit: on any branch when `global`, otherwise
+    /// along the current root branch only. `dates` holds each row's tip date
+    /// as a decimal year; tips below a `hidden` row are left out.
+    Temporal {
+        global: bool,
+        #[serde(default)]
+        dates: Vec<Option<f64>>,
+        #[serde(default)]
+        hidden: Vec<usize>,
+    },
+}
+
+/// A re-rooted tree and, for each of its rows, the input row it came from
+/// (`None` for the new root).
+#[derive(Debug, Clone, Serialize)]
+pub struct Rerooted {
+    pub tree: Tree,
+    pub source: Vec<Option<usize>>,
+}
+
+/// Re-roots `tree` as `method` asks. Annotations named in `branch_keys`
+/// describe the branch above their node and move with it, as in
+/// `rerootOnGraph`.
+pub fn reroot(tree: &Tree, method: &RootMethod, branch_keys: &[String]) -> Result<Rerooted, String> {
+    let graph = Unrooted::new(tree)?;
+    let (parent, child, distance) = match method {
+        RootMethod::Branch { node, distance } => {
+            let parent = tree.nodes.get(*node).and_then(|n| n.parent).and_then(|_| graph.adj[*node].first());
+            let &(parent, _) = parent.ok_or("no branch above that node")?;
+            (parent, *node, *distance)
+        }
+        RootMethod::Midpoint => graph.midpoint(),
+        RootMethod::Temporal { global, dates, hidden } => graph.temporal_root(tree, dates, hidden, *global),
+    };
+    Ok(graph.rooted_at(tree, parent, child, distance, branch_keys))
+}
+
+/// The tree as an undirected graph, with the virtual root (if any) dropped.
+struct Unrooted {
+    /// Neighbours of each row with the length of the edge to them. The
+    /// parent comes first; for the children of a dropped root, the other child.
+    adj: Vec<Vec<(usize, f64)>>,
+    /// The edge the tree is rooted on, parent side first.
+    root_edge: (usize, usize),
+}
+
+impl Unrooted {
+    fn new(tree: &Tree) -> Result<Self, String> {
+        let n = tree.nodes.len();
+        if n < 3 || tree.nodes[0].parent.is_some() {
+            return Err("tree has too few nodes to re-root".into());
+        }
+        let mut children = vec![Vec::new(); n];
+        for (i, node) in tree.nodes.iter().enumerate() {
+            if let Some(p) = node.parent {
+                children[p].push(i);
+            }
+        }
+        let len = |i: usize| tree.nodes[i].length.filter(|l| l.is_finite()).unwrap_or(0.0);
+        let dropped = (children[0].len() == 2).then_some(0);
+        let mut adj = vec![Vec::new(); n];
+        for (i, node) in tree.nodes.iter().enumerate() {
+            match node.parent {
+                Some(0) if dropped.is_some() => {
+                    let (a, b) = (children[0][0], children[0][1]);
+                    adj[i].push((if i == a { b } else { a }, len(a) + len(b)));
+                }
+                Some(p) => adj[i].push((p, len(i))),
+                None => {}
+            }
+            if dropped != Some(i) {
+                adj[i].extend(children[i].iter().map(|&c| (c, len(c))));
+            }
+        }
+        let root_edge = match children[0][..] {
+            [a, b] => (a, b),
+            [first, ..] => (0, first),
+            [] => return Err("tree has too few nodes to re-root".into()),
+        };
+        Ok(Unrooted { adj, root_edge })
+    }
+
+    fn edge_length(&self, a: usize, b: usize) -> f64 {
+        self.adj[a].iter().find(|&&(n, _)| n == b).map_or(0.0, |&(_, l)| l)
+    }
+
+    /// Builds the node table rooted on edge `parent`–`child`, `distance`
+    /// from the parent end.
+    fn rooted_at(&self, tree: &Tree, parent: usize, child: usize, distance: f64, branch_keys: &[String]) -> Rerooted {
+        let total = self.edge_length(parent, child);
+        // Keep the endpoint that was the child below the root, as rerootOnGraph does.
+        let (parent, child, distance) = if tree.nodes[parent].parent == Some(child) {
+            (child, parent, total - distance)
+        } else {
+            (parent, child, distance)
+        };
+        let distance = distance.max(0.0).min(total);
+
+        let mut out = Tree { name: tree.name.clone(), rooted: Some(true), nodes: vec![TreeNode::default()] };
+        let mut source = vec![None];
+        // (row in `tree`, the neighbour it hangs from, branch length, parent row in `out`)
+        let mut stack = vec![(child, parent, total - distance, 0), (parent, child, distance, 0)];
+        while let Some((n, from, length, parent_row)) = stack.pop() {
+            // Which node held the annotations of this branch before: its old
+            // child. On the new root's two branches only the child side keeps them.
+            let holder = if parent_row == 0 {
+                (n == child).then_some(n)
+            } else if tree.nodes[from].parent == Some(n) {
+                Some(from)
+            } else {
+                Some(n)
+            };
+            let mut annotations = tree.nodes[n].annotations.clone();
+            for key in branch_keys {
+                annotations.remove(key);
+                if let Some(value) = holder.and_then(|h| tree.nodes[h].annotations.get(key)) {
+                    annotations.insert(key.clone(), value.clone());
+                }
+            }
+            let row = out.nodes.len();
+            out.nodes.push(TreeNode {
+                parent: Some(parent_row),
+                length: Some(length),
+                label: tree.nodes[n].label.clone(),
+                annotations,
+            });
+            source.push(Some(n));
+            for &(c, l) in self.adj[n].iter().rev() {
+                if c != from {
+                    stack.push((c, n, l, row));
+                }
+            }
+        }
+        Rerooted { tree: out, source }
+    }
+
+    /// Distance from `start` to every node, and the previous node on the way.
+    fn distances_from(&self, start: usize) -> (Vec<f64>, Vec<Option<usize>>) {
+        let mut dist = vec![f64::NAN; self.adj.len()];
+        let mut prev = vec![None; self.adj.len()];
+        dist[start] = 0.0;
+        let mut stack = vec![start];
+        while let Some(n) = stack.pop() {
+            for &(c, l) in &self.adj[n] {
+                if dist[c].is_nan() {
+                    dist[c] = dist[n] + l;
+                    prev[c] = Some(n);
+                    stack.push(c);
+                }
+            }
+        }
+        (dist, prev)
+    }
+
+    /// The midpoint of the longest tip-to-tip path, as (parent side, child
+    /// side, distance from the parent side).
+    fn midpoint(&self) -> (usize, usize, f64) {
+        let tips: Vec<usize> = (0..self.adj.len()).filter(|&n| self.adj[n].len() == 1).collect();
+        let farthest = |dist: &[f64]| tips.iter().copied().max_by(|&a, &b| dist[a].total_cmp(&dist[b]));
+        let (from_first, _) = self.distances_from(tips[0]);
+        let tip_a = farthest(&from_first).unwrap_or(tips[0]);
+        let (dist, prev) = self.distances_from(tip_a);
+        let tip_b = farthest(&dist).unwrap_or(tip_a);
+        let half = dist[tip_b] / 2.0;
+
+        // Walk back from tip_b; the midpoint is on the first edge reaching below `half`.
+        let mut n = tip_b;
+        while let Some(p) = prev[n] {
+            if dist[p] <= half {
+                return (p, n, half - dist[p]);
+            }
+            n = p;
+        }
+        let (a, b) = self.root_edge;
+        (a, b, self.edge_length(a, b) / 2.0)
+    }
+
+    /// The best root for a root-to-tip regression. See `RootMethod::Temporal`.
+    fn temporal_root(&self, tree: &Tree, dates: &[Option<f64>], hidden: &[usize], global: bool) -> (usize, usize, f64) {
+        let (anchor, root_child) = self.root_edge;
+        let fallback = (anchor, root_child, self.edge_length(anchor, root_child) / 2.0);
+        let sums = Sums::new(self, tree, anchor, dates, hidden);
+        if sums.n < 2.0 {
+            return fallback;
+        }
+        let candidates: Vec<usize> = if global {
+            sums.order.iter().copied().filter(|&c| c != anchor).collect()
+        } else {
+            vec![root_child]
+        };
+        let mut best: Option<(usize, f64, f64)> = None;
+        for c in candidates {
+            if let Some((d, score)) = sums.evaluate(c) {
+                if best.is_none_or(|(_, _, s)| score < s) {
+                    best = Some((c, d, score));
+                }
+            }
+        }
+        match best {
+            Some((c, d, _)) => (sums.parent[c], c, d),
+            None => fallback,
+        }
+    }
+}
+
+/// Per-node sums over the tips for the temporal-root search, gathered from
+/// an anchor node. Distances below a node are measured from that node, so
+/// long trees lose no precision to cancellation.
+struct Sums {
+    /// Pre-order from the anchor.
+    order: Vec<usize>,
+    /// Neighbour towards the anchor, and the length of the edge to it.
+    parent: Vec<usize>,
+    length: Vec<f64>,
+    /// Number of tips used, and whether they are undated.
+    n: f64,
+    contemporaneous: bool,
+    sum_t: f64,
+    t_bar: f64,
+    /// Sum of squared deviations of the tip dates.
+    ss_t: f64,
+    /// For the tips below each node: count, sum of dates, and sums of
+    /// distance, squared distance and date × distance from the node.
+    sub_n: Vec<f64>,
+    sub_t: Vec<f64>,
+    sub_y: Vec<f64>,
+    sub_yy: Vec<f64>,
+    sub_ty: Vec<f64>,
+    /// The same distance sums from each node over all tips.
+    total_y: Vec<f64>,
+    total_yy: Vec<f64>,
+    total_ty: Vec<f64>,
+}
+
+impl Sums {
+    fn new(graph: &Unrooted, tree: &Tree, anchor: usize, dates: &[Option<f64>], hidden: &[usize]) -> Self {
+        let size = graph.adj.len();
+        let mut excluded = vec![false; size];
+        for &h in hidden.iter().filter(|&&h| h < size) {
+            excluded[h] = true;
+        }
+        for i in 0..size {
+            if let Some(p) = tree.nodes[i].parent {
+                excluded[i] |= excluded[p];
+            }
+        }
+        let contemporaneous = dates.iter().all(Option::is_none);
+        let date = |i: usize| dates.get(i).copied().flatten();
+        let is_tip = |i: usize| graph.adj[i].len() == 1 && !excluded[i] && (contemporaneous || date(i).is_some());
+
+        let mut order = Vec::with_capacity(size);
+        let mut parent = vec![usize::MAX; size];
+        let mut length = vec![0.0; size];
+        let mut stack = vec![anchor];
+        while let Some(n) = stack.pop() {
+            order.push(n);
+            for &(c, l) in graph.adj[n].iter().rev() {
+                if c != parent[n] {
+                    parent[c] = n;
+                    length[c] = l;
+                    stack.push(c);
+                }
+            }
+        }
+
+        let mut s = Sums {
+            parent,
+            length,
+            n: 0.0,
+            contemporaneous,
+            sum_t: 0.0,
+            t_bar: 0.0,
+            ss_t: 0.0,
+            sub_n: vec![0.0; size],
+            sub_t: vec![0.0; size],
+            sub_y: vec![0.0; size],
+            sub_yy: vec![0.0; size],
+            sub_ty: vec![0.0; size],
+            total_y: vec![0.0; size],
+            total_yy: vec![0.0; size],
+            total_ty: vec![0.0; size],
+            order,
+        };
+        let mut sum_tt = 0.0;
+        for &n in s.order.iter().rev() {
+            if is_tip(n) {
+                let t = date(n).unwrap_or(0.0);
+                s.sub_n[n] = 1.0;
+                s.sub_t[n] = t;
+                s.n += 1.0;
+                s.sum_t += t;
+                sum_tt += t * t;
+            }
+            if n == anchor {
+                continue;
+            }
+            // Fold n into its parent, moving its sums up the edge of length e.
+            let (p, e) = (s.parent[n], s.length[n]);
+            let (nc, y) = (s.sub_n[n], s.sub_y[n]);
+            s.sub_n[p] += nc;
+            s.sub_t[p] += s.sub_t[n];
+            s.sub_y[p] += y + nc * e;
+            s.sub_yy[p] += s.sub_yy[n] + 2.0 * e * y + nc * e * e;
+            s.sub_ty[p] += s.sub_ty[n] + e * s.sub_t[n];
+        }
+        if s.n > 0.0 {
+            s.t_bar = s.sum_t / s.n;
+            s.ss_t = sum_tt - s.sum_t * s.sum_t / s.n;
+        }
+
+        s.total_y[anchor] = s.sub_y[anchor];
+        s.total_yy[anchor] = s.sub_yy[anchor];
+        s.total_ty[anchor] = s.sub_ty[anchor];
+        for i in 1..s.order.len() {
+            // Moving the reference point from p down to c brings the tips
+            // below c closer by e and takes the rest further away.
+            let c = s.order[i];
+            let (p, e, nc) = (s.parent[c], s.length[c], s.sub_n[c]);
+            let below_from_p = s.sub_y[c] + nc * e;
+            s.total_y[c] = s.total_y[p] + (s.n - 2.0 * nc) * e;
+            s.total_yy[c] = s.total_yy[p] + 2.0 * e * (s.total_y[p] - 2.0 * below_from_p) + s.n * e * e;
+            s.total_ty[c] = s.total_ty[p] + e * (s.sum_t - 2.0 * s.sub_t[c]);
+        }
+        s
+    }
+
+    /// The best root position on the edge above `c` (as distance from its
+    /// parent end) with its score, keeping the clock rate positive. `None`
+    /// when the edge has no length or no tips on one side.
+    fn evaluate(&self, c: usize) -> Option<(f64, f64)> {
+        let (n, l) = (self.n, self.length[c]);
+        let nd = self.sub_n[c];
+        let np = n - nd;
+        if l <= 0.0 || nd == 0.0 || np == 0.0 {
+            return None;
+        }
+        // Sums for the tips below c (side B) from c, and for the rest (side P)
+        // from the parent end, derived from the totals at c.
+        let (sum_rb, sum_rrb, sum_trb, sum_tb) = (self.sub_y[c], self.sub_yy[c], self.sub_ty[c], self.sub_t[c]);
+        let sum_tp = self.sum_t - sum_tb;
+        let sum_rp = self.total_y[c] - sum_rb - np * l;
+        let sum_rrp = self.total_yy[c] - sum_rrb - 2.0 * l * sum_rp - np * l * l;
+        let sum_trp = self.total_ty[c] - sum_trb - l * sum_tp;
+
+        // Root-to-tip heights with the root at the parent end (d = 0); the
+        // spread of heights is b0 + b1·d + b2·d² as the root moves down by d.
+        let sum_hb0 = sum_rb + nd * l;
+        let sum_hb0_sq = sum_rrb + 2.0 * l * sum_rb + nd * l * l;
+        let m0 = (sum_rp + sum_hb0) / n;
+        let b2 = 4.0 * nd * np / n;
+        if b2 * l <= 1e-20 {
+            return None;
+        }
+        let b0 = (sum_rrp - 2.0 * m0 * sum_rp + np * m0 * m0) + (sum_hb0_sq - 2.0 * m0 * sum_hb0 + nd * m0 * m0);
+        let b1 = 2.0 * ((sum_rp - np * m0) - (sum_hb0 - nd * m0));
+
+        if self.contemporaneous || self.ss_t <= 1e-20 {
+            let d = (-b1 / (2.0 * b2)).clamp(0.0, l);
+            return Some((d, (b0 + b1 * d + b2 * d * d) / n));
+        }
+        // Dated tips: the date–height covariance is a0 + a1·d; minimise the
+        // regression's residual sum of squares over the positive-rate range.
+        let a0 = (sum_trp + sum_trb + l * sum_tb) - self.t_bar * (sum_rp + sum_hb0);
+        let a1 = (sum_tp - np * self.t_bar) - (sum_tb - nd * self.t_bar);
+        let (mut lo, mut hi) = (0.0, l);
+        if a1 > 1e-20 {
+            lo = f64::max(lo, -a0 / a1);
+        } else if a1 < -1e-20 {
+            hi = f64::min(hi, -a0 / a1);
+        } else if a0 <= 0.0 {
+            return None;
+        }
+        if lo >= hi {
+            return None;
+        }
+        let ss_t = self.ss_t;
+        let residual = |d: f64| b0 + b1 * d + b2 * d * d - (a0 + a1 * d).powi(2) / ss_t;
+        let q2 = b2 - a1 * a1 / ss_t;
+        let q1 = b1 - 2.0 * a0 * a1 / ss_t;
+        let d = if q2 > 1e-30 {
+            (-q1 / (2.0 * q2)).clamp(lo, hi)
+        } else if residual(lo) <= residual(hi) {
+            lo
+        } else {
+            hi
+        };
+        Some((d, residual(d) / n))
+    }
+}
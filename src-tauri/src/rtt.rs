@@ -0,0 +1,326 @@
+//! Root-to-tip regression of divergence on tip dates, and the CSV and
+//! summary report exported from the RTT panel.
+//!
+//! The fit is the ordinary least-squares line the RTT panel draws: the slope
+//! is the clock rate and the x-intercept the date of the root (TMRCA). Tips
+//! without a date are listed but left out of the fit. Residuals are
+//! divergence minus the line, and z-scores divide them by the root mean
+//! square residual, as `computeTemporalResiduals` does for the tree's
+//! residual annotations.
+//!
+//! The date-randomisation test checks for temporal signal by refitting the
+//! rate with the tip dates shuffled: either between tips, or between
+//! clusters of tips sampled on the same date, which keeps same-day samples
+//! of an outbreak together (Duchêne et al. 2015). Real signal puts the
+//! observed rate above most of the rates of the permuted dates.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// One tip of the RTT plot.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TipPoint {
+    pub name: String,
+    /// The date as shown in the panel, written to the CSV as is.
+    #[serde(default)]
+    pub date: Option<String>,
+    /// The date as a decimal year; `None` for undated tips.
+    pub decimal_date: Option<f64>,
+    /// Root-to-tip distance.
+    pub divergence: f64,
+}
+
+/// A root-to-tip regression.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Regression {
+    /// Number of dated tips fitted.
+    pub n: usize,
+    /// Slope: substitutions per site per year.
+    pub rate: f64,
+    /// Divergence at year 0.
+    pub intercept: f64,
+    /// Where the line reaches zero divergence, as a decimal year.
+    pub tmrca: Option<f64>,
+    /// Correlation coefficient of date and divergence.
+    pub r: f64,
+    pub r2: f64,
+    /// Residual sum of squares over `n - 2`, as TempEst reports it.
+    pub residual_mean_square: f64,
+    /// Root mean square residual, the scale of the z-scores.
+    pub rmse: f64,
+    /// Coefficient of variation of the root-to-tip divergences.
+    pub cv: f64,
+}
+
+impl Regression {
+    /// Fits `(date, divergence)` pairs, or `None` with fewer than two
+    /// distinct dates.
+    pub fn fit(points: &[(f64, f64)]) -> Option<Regression> {
+        let n = points.len();
+        if n < 2 {
+            return None;
+        }
+        let nf = n as f64;
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / nf;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / nf;
+        let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+        for &(x, y) in points {
+            sxx += (x - mean_x) * (x - mean_x);
+            syy += (y - mean_y) * (y - mean_y);
+            sxy += (x - mean_x) * (y - mean_y);
+        }
+        if sxx <= 0.0 {
+            return None;
+        }
+        let rate = sxy / sxx;
+        let intercept = mean_y - rate * mean_x;
+        let rss = (syy - sxy * sxy / sxx).max(0.0);
+        let r = if syy > 0.0 { sxy / (sxx * syy).sqrt() } else { 0.0 };
+        Some(Regression {
+            n,
+            rate,
+            intercept,
+            tmrca: (rate != 0.0).then(|| -intercept / rate),
+            r,
+            r2: r * r,
+            residual_mean_square: if n > 2 { rss / (nf - 2.0) } else { 0.0 },
+            rmse: (rss / nf).sqrt(),
+            cv: if mean_y != 0.0 { (syy / nf).sqrt() / mean_y } else { 0.0 },
+        })
+    }
+
+    /// Divergence minus the line at `date`.
+    pub fn residual(&self, date: f64, divergence: f64) -> f64 {
+        divergence - (self.rate * date + self.intercept)
+    }
+
+    /// A residual in units of the root mean square residual.
+    pub fn z_score(&self, residual: f64) -> f64 {
+        if self.rmse > 0.0 { residual / self.rmse } else { 0.0 }
+    }
+}
+
+/// The regression of a set of tips, with each tip's residual (`None` for
+/// undated tips).
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub regression: Regression,
+    pub residuals: Vec<Option<f64>>,
+}
+
+/// Regresses the dated tips of `points`.
+pub fn regress(points: &[TipPoint]) -> Result<Report, String> {
+    let dated: Vec<(f64, f64)> =
+        points.iter().filter_map(|p| Some((p.decimal_date.filter(|d| d.is_finite())?, p.divergence))).collect();
+    let regression = Regression::fit(&dated).ok_or("Root-to-tip regression needs at least two tips with different dates")?;
+    let residuals = points
+        .iter()
+        .map(|p| p.decimal_date.filter(|d| d.is_finite()).map(|d| regression.residual(d, p.divergence)))
+        .collect();
+    Ok(Report { regression, residuals })
+}
+
+/// Options of the date-randomisation test.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateTestOptions {
+    /// Number of permutations.
+    pub replicates: usize,
+    /// Shuffle dates between clusters of tips with the same date rather than
+    /// between single tips.
+    #[serde(default)]
+    pub clustered: bool,
+    /// Seed for the permutations; taken from the clock when absent.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Outcome of a date-randomisation test.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateTest {
+    /// Rate fitted to the real dates.
+    pub rate: f64,
+    /// Rates fitted to each permutation, in ascending order.
+    pub null_rates: Vec<f64>,
+    /// Share of permutations (counting the real dates as one) whose rate is
+    /// at least the real rate.
+    pub p_value: f64,
+    /// 2.5% and 97.5% quantiles of the permuted rates.
+    pub null_low: f64,
+    pub null_high: f64,
+    pub clustered: bool,
+    /// Number of units shuffled: dated tips, or date clusters.
+    pub clusters: usize,
+}
+
+/// Runs the date-randomisation test on the dated tips of `points`.
+/// `progress(done, total)` is called as permutations finish and stops the
+/// test, returning `None`, when it returns false.
+pub fn date_randomisation(
+    points: &[TipPoint],
+    options: &DateTestOptions,
+    mut progress: impl FnMut(usize, usize) -> bool,
+) -> Result<Option<DateTest>, String> {
+    if options.replicates == 0 {
+        return Err("The date-randomisation test needs at least one replicate".into());
+    }
+    let mut dated: Vec<(f64, f64)> =
+        points.iter().filter_map(|p| Some((p.decimal_date.filter(|d| d.is_finite())?, p.divergence))).collect();
+    let rate = Regression::fit(&dated).ok_or("Root-to-tip regression needs at least two tips with different dates")?.rate;
+
+    // Each tip is a cluster of its own unless clustering by date.
+    let mut cluster_of = Vec::with_capacity(dated.len());
+    let mut cluster_dates: Vec<f64> = Vec::new();
+    let mut by_date: HashMap<u64, usize> = HashMap::new();
+    for &(date, _) in &dated {
+        let mut new_cluster = || {
+            cluster_dates.push(date);
+            cluster_dates.len() - 1
+        };
+        let c = if options.clustered { *by_date.entry(date.to_bits()).or_insert_with(new_cluster) } else { new_cluster() };
+        cluster_of.push(c);
+    }
+
+    let seed = options.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+    });
+    let mut rng = SplitMix64(seed);
+    let step = (options.replicates / 100).max(1);
+    let mut null_rates = Vec::with_capacity(options.replicates);
+    let mut shuffled = cluster_dates.clone();
+    for i in 0..options.replicates {
+        for j in (1..shuffled.len()).rev() {
+            shuffled.swap(j, rng.below(j + 1));
+        }
+        for (point, &c) in dated.iter_mut().zip(&cluster_of) {
+            point.0 = shuffled[c];
+        }
+        null_rates.push(Regression::fit(&dated).map_or(0.0, |r| r.rate));
+        if (i + 1) % step == 0 && !progress(i + 1, options.replicates) {
+            return Ok(None);
+        }
+    }
+    null_rates.sort_by(f64::total_cmp);
+
+    let at_least = null_rates.iter().filter(|&&r| r >= rate).count();
+    let quantile = |q: f64| null_rates[((null_rates.len() - 1) as f64 * q).round() as usize];
+    Ok(Some(DateTest {
+        rate,
+        p_value: (at_least + 1) as f64 / (null_rates.len() + 1) as f64,
+        null_low: quantile(0.025),
+        null_high: quantile(0.975),
+        clustered: options.clustered,
+        clusters: cluster_dates.len(),
+        null_rates,
+    }))
+}
+
+/// SplitMix64, a small generator that is plenty for shuffling dates.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Writes one row per tip: name, date, decimal date, divergence, the fitted
+/// value, residual and z-score.
+pub fn write_csv<W: Write>(out: &mut W, points: &[TipPoint], report: &Report) -> io::Result<()> {
+    let reg = &report.regression;
+    writeln!(out, "name,date,date (decimal year),divergence,regression,residual,z-score")?;
+    for (p, residual) in points.iter().zip(&report.residuals) {
+        let decimal = p.decimal_date.map(|d| d.to_string()).unwrap_or_default();
+        let (fitted, residual, z) = match (p.decimal_date, residual) {
+            (Some(d), Some(r)) => ((reg.rate * d + reg.intercept).to_string(), r.to_string(), reg.z_score(*r).to_string()),
+            _ => Default::default(),
+        };
+        let date = p.date.as_deref().unwrap_or_default();
+        let row = [csv_field(&p.name), csv_field(date), decimal, p.divergence.to_string(), fitted, residual, z];
+        writeln!(out, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// Writes the regression as a plain-text summary, listing tips more than
+/// two root mean square residuals off the line.
+pub fn write_summary<W: Write>(
+    out: &mut W,
+    points: &[TipPoint],
+    report: &Report,
+    date_key: Option<&str>,
+) -> io::Result<()> {
+    let reg = &report.regression;
+    writeln!(out, "Root-to-tip regression")?;
+    writeln!(out)?;
+    if let Some(key) = date_key {
+        writeln!(out, "Date annotation:        {key}")?;
+    }
+    writeln!(out, "Tips:                   {} ({} dated)", points.len(), reg.n)?;
+    writeln!(out, "Rate (slope):           {:.6e} /yr", reg.rate)?;
+    match reg.tmrca {
+        Some(t) => writeln!(out, "TMRCA (x-intercept):    {t:.4} ({})", calendar_date(t))?,
+        None => writeln!(out, "TMRCA (x-intercept):    -")?,
+    }
+    writeln!(out, "Intercept (year 0):     {:.6e}", reg.intercept)?;
+    writeln!(out, "Correlation (r):        {:.4}", reg.r)?;
+    writeln!(out, "R²:                     {:.4}", reg.r2)?;
+    writeln!(out, "Residual mean square:   {:.6e}", reg.residual_mean_square)?;
+    writeln!(out, "RMSE:                   {:.6e}", reg.rmse)?;
+    writeln!(out, "Coefficient of var.:    {:.4}", reg.cv)?;
+
+    let outliers: Vec<(&TipPoint, f64)> = points
+        .iter()
+        .zip(&report.residuals)
+        .filter_map(|(p, r)| Some((p, (*r)?)))
+        .filter(|&(_, r)| reg.z_score(r).abs() > 2.0)
+        .collect();
+    writeln!(out)?;
+    writeln!(out, "Outliers (|z| > 2):     {}", outliers.len())?;
+    for (p, r) in outliers {
+        writeln!(out, "  {}\tresidual {:.6e}\tz {:.3}", p.name, r, reg.z_score(r))?;
+    }
+    Ok(())
+}
+
+/// `yyyy-mm-dd` for a decimal year, rounding to the nearest day as the
+/// frontend's `decYearToDate` does.
+pub fn calendar_date(decimal_year: f64) -> String {
+    let year = decimal_year.floor() as i64;
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_year = if leap { 366 } else { 365 };
+    let mut day = (((decimal_year - year as f64) * days_in_year as f64).round() as i64 + 1).clamp(1, days_in_year);
+    let months = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut month = 1;
+    for &days in &months[..11] {
+        if day <= days {
+            break;
+        }
+        day -= days;
+        month += 1;
+    }
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Quotes a CSV field when it holds a comma, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
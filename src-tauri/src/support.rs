@@ -0,0 +1,108 @@
+//! Support values written as internal node labels.
+//!
+//! IQ-TREE puts several support measures into one label: `95/100` for
+//! SH-aLRT and UFBoot, `95/0.98/100` with aBayes in between, and its
+//! concordance-factor trees (`.cf.tree`) write `100/85.2/71.4` for
+//! bootstrap, gCF and sCF. When every labelled internal node of a tree has
+//! the same number of `/`-separated numbers, they are split into numeric
+//! annotations and the label is dropped.
+//!
+//! A plain number is only named here when the file name says where it came
+//! from (`.contree`, `.treefile`, `.support`, MrBayes `.con.tre`); otherwise
+//! the frontend asks the user what to call it, as before.
+
+use crate::fileio::COMPRESSED_EXTENSIONS;
+use crate::newick::typed_value;
+use crate::tree::Tree;
+use std::path::Path;
+
+/// What wrote a tree file, as far as its name tells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LabelOrigin {
+    #[default]
+    Unknown,
+    /// IQ-TREE's maximum-likelihood tree (`.treefile`).
+    IqTree,
+    /// IQ-TREE's bootstrap consensus tree (`.contree`).
+    IqTreeConsensus,
+    /// IQ-TREE's concordance-factor tree (`.cf.tree`).
+    IqTreeConcordance,
+    /// RAxML-NG's tree with support values mapped on (`.support`).
+    Raxml,
+    /// MrBayes' consensus tree (`.con.tre`), written with `conformat=simple`.
+    MrBayes,
+}
+
+impl LabelOrigin {
+    /// Recognises the file from its name, looking through a compression
+    /// suffix (`run.treefile.gz`).
+    pub fn from_path(path: &Path) -> Self {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_ascii_lowercase();
+        let name = COMPRESSED_EXTENSIONS
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext).and_then(|n| n.strip_suffix('.')))
+            .unwrap_or(&name);
+        if name.ends_with(".cf.tree") {
+            LabelOrigin::IqTreeConcordance
+        } else if name.ends_with(".contree") {
+            LabelOrigin::IqTreeConsensus
+        } else if name.ends_with(".treefile") {
+            LabelOrigin::IqTree
+        } else if name.ends_with(".support") {
+            LabelOrigin::Raxml
+        } else if name.ends_with(".con.tre") {
+            LabelOrigin::MrBayes
+        } else {
+            LabelOrigin::Unknown
+        }
+    }
+
+    /// Annotation names for labels of `count` values, or `None` to leave a
+    /// single unexplained value to the frontend.
+    fn names(self, count: usize) -> Option<Vec<String>> {
+        let names: &[&str] = match (self, count) {
+            (LabelOrigin::IqTreeConcordance, 2) => &["gcf", "scf"],
+            (LabelOrigin::IqTreeConcordance, 3) => &["bootstrap", "gcf", "scf"],
+            (LabelOrigin::IqTreeConsensus, 1) => &["bootstrap"],
+            (LabelOrigin::IqTree | LabelOrigin::Raxml, 1) => &["support"],
+            (LabelOrigin::MrBayes, 1) => &["prob"],
+            (_, 1) => return None,
+            (_, 2) => &["sh_alrt", "ufboot"],
+            (_, 3) => &["sh_alrt", "abayes", "ufboot"],
+            _ => return Some((1..=count).map(|i| format!("support_{i}")).collect()),
+        };
+        Some(names.iter().map(|n| n.to_string()).collect())
+    }
+}
+
+/// Splits numeric internal node labels of `tree` into named support
+/// annotations. Trees whose labels are not all numbers, or not all the same
+/// shape, are left alone.
+pub fn split_support_labels(tree: &mut Tree, origin: LabelOrigin) {
+    let mut has_child = vec![false; tree.nodes.len()];
+    for node in &tree.nodes {
+        if let Some(p) = node.parent {
+            has_child[p] = true;
+        }
+    }
+    let labelled: Vec<usize> =
+        (0..tree.nodes.len()).filter(|&n| has_child[n] && tree.nodes[n].label.is_some()).collect();
+    let Some(&first) = labelled.first() else { return };
+    let parts = |n: usize| -> Vec<&str> { tree.nodes[n].label.as_deref().unwrap_or_default().split('/').collect() };
+    let count = parts(first).len();
+    let numeric = labelled.iter().all(|&n| {
+        let values = parts(n);
+        values.len() == count && values.iter().all(|v| v.trim().parse::<f64>().is_ok())
+    });
+    if !numeric {
+        return;
+    }
+    let Some(names) = origin.names(count) else { return };
+    for n in labelled {
+        let node = &mut tree.nodes[n];
+        let label = node.label.take().unwrap_or_default();
+        for (name, value) in names.iter().zip(label.split('/')) {
+            node.annotations.insert(name.clone(), typed_value(value.trim()));
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Taxonium JSONL reader.
+//!
+//! A Taxonium file is one JSON object per line: a header (with the shared
+//! `mutations` table and `total_nodes`) and then one object per node, linked
+//! by `node_id` / `parent_id` (the root is its own parent). Layout fields
+//! (`x_dist`, `x_time`, `y`, `num_tips`) are dropped; branch lengths come
+//! from the cumulative `x_dist`, or `x_time` when that is all there is.
+//!
+//! Metadata columns (`meta_country`, ...) become annotations without the
+//! `meta_` prefix. A node's mutations are looked up in the header table (by
+//! `mutation_id`):
+//! nucleotide changes go to `mutations` (`C241T`) and amino-acid changes to
+//! `aa_mutations` (`S:D614G`).
+
+use crate::newick::ParseError;
+use crate::tree::Tree;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Node fields that describe the layout or the links rather than the node.
+const LAYOUT_FIELDS: &[&str] = &["node_id", "parent_id", "x_dist", "x_time", "y", "num_tips", "mutations", "name"];
+
+/// Returns true when `text` looks like Taxonium JSONL: a JSON object per
+/// line, with nodes carrying `node_id` and `parent_id`.
+pub fn is_taxonium(text: &str) -> bool {
+    let mut lines = text.trim_start_matches('\u{feff}').lines().filter(|l| !l.trim().is_empty());
+    let is_node = |line: &str| line.trim_start().starts_with('{') && line.contains("\"node_id\"") && line.contains("\"parent_id\"");
+    match lines.next() {
+        Some(first) if is_node(first) => true,
+        Some(first) if first.trim_start().starts_with('{') && first.contains("\"total_nodes\"") => lines.next().is_some_and(is_node),
+        _ => false,
+    }
+}
+
+struct Row {
+    id: i64,
+    parent: i64,
+    x_dist: Option<f64>,
+    x_time: Option<f64>,
+    label: Option<String>,
+    annotations: Map<String, Value>,
+}
+
+/// Reads the tree from a Taxonium JSONL file.
+pub fn parse_taxonium(text: &str) -> Result<Tree, ParseError> {
+    let mut mutation_table: HashMap<u64, (String, String)> = HashMap::new();
+    let mut rows = Vec::new();
+    let mut offset = 0;
+    for (line_no, line) in text.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_start_matches('\u{feff}');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object: Map<String, Value> = serde_json::from_str(line)
+            .map_err(|e| ParseError::at_line(text, line_no + 1, e.column(), e.to_string()))?;
+        if !object.contains_key("node_id") {
+            if let Some(Value::Array(table)) = object.get("mutations") {
+                mutation_table = table
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| (m.get("mutation_id").and_then(Value::as_u64).unwrap_or(i as u64), describe_mutation(m)))
+                    .collect();
+            }
+            continue;
+        }
+        let id = |key: &str| {
+            object.get(key).and_then(Value::as_i64).ok_or_else(|| ParseError::new(start, format!("node has no integer \"{key}\"")))
+        };
+        let (id, parent) = (id("node_id")?, id("parent_id")?);
+        let x_dist = object.get("x_dist").and_then(Value::as_f64);
+        let x_time = object.get("x_time").and_then(Value::as_f64);
+        let label = object.get("name").and_then(Value::as_str).filter(|s| !s.is_empty()).map(str::to_string);
+        let mut annotations = Map::new();
+        let (mut nt, mut aa) = (Vec::new(), Vec::new());
+        for m in object.get("mutations").and_then(Value::as_array).into_iter().flatten() {
+            let Some((gene, change)) = m.as_u64().and_then(|i| mutation_table.get(&i)) else { continue };
+            if gene == "nt" {
+                nt.push(Value::from(change.as_str()));
+            } else {
+                aa.push(Value::from(format!("{gene}:{change}")));
+            }
+        }
+        for (key, value) in object {
+            if LAYOUT_FIELDS.contains(&key.as_str()) || value.is_null() || value == "" {
+                continue;
+            }
+            let key = key.strip_prefix("meta_").map_or(key.clone(), str::to_string);
+            annotations.insert(key, value);
+        }
+        if !nt.is_empty() {
+            annotations.insert("mutations".into(), Value::Array(nt));
+        }
+        if !aa.is_empty() {
+            annotations.insert("aa_mutations".into(), Value::Array(aa));
+        }
+        rows.push(Row { id, parent, x_dist, x_time, label, annotations });
+    }
+    build_tree(rows)
+}
+
+/// `{gene, previous_residue, residue_pos, new_residue}` as `(gene, "D614G")`.
+fn describe_mutation(m: &Value) -> (String, String) {
+    let field = |key: &str| match m.get(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    };
+    let change = format!("{}{}{}", field("previous_residue"), field("residue_pos"), field("new_residue"));
+    (field("gene"), change)
+}
+
+/// Links the rows into a pre-order node table under the root.
+fn build_tree(rows: Vec<Row>) -> Result<Tree, ParseError> {
+    let mut index: HashMap<i64, usize> = HashMap::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        if index.insert(row.id, i).is_some() {
+            return Err(ParseError::new(0, format!("node_id {} appears more than once", row.id)));
+        }
+    }
+    let mut children = vec![Vec::new(); rows.len()];
+    let mut roots = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        if row.parent == row.id {
+            roots.push(i);
+            continue;
+        }
+        let p = *index
+            .get(&row.parent)
+            .ok_or_else(|| ParseError::new(0, format!("node {} has unknown parent_id {}", row.id, row.parent)))?;
+        children[p].push(i);
+    }
+    let root = match roots[..] {
+        [root] => root,
+        [] => return Err(ParseError::new(0, "no root node (a node that is its own parent) found")),
+        _ => return Err(ParseError::new(0, format!("{} root nodes found; expected one", roots.len()))),
+    };
+
+    let by_dist = rows.iter().any(|r| r.x_dist.is_some());
+    let mut slots: Vec<Option<Row>> = rows.into_iter().map(Some).collect();
+    let mut tree = Tree { rooted: Some(true), ..Default::default() };
+    let mut positions = Vec::with_capacity(slots.len());
+    let mut stack = vec![(root, None)];
+    while let Some((i, parent)) = stack.pop() {
+        let row = slots[i].take().expect("each node is reached once");
+        let n = tree.push_node(parent);
+        let node = &mut tree.nodes[n];
+        node.label = row.label;
+        node.annotations = row.annotations;
+        positions.push(if by_dist { row.x_dist } else { row.x_time });
+        for &c in children[i].iter().rev() {
+            stack.push((c, Some(n)));
+        }
+    }
+    let unreached = slots.iter().filter(|s| s.is_some()).count();
+    if unreached > 0 {
+        return Err(ParseError::new(0, format!("{unreached} nodes are not connected to the root")));
+    }
+    for n in 1..tree.nodes.len() {
+        if let (Some(x), Some(p)) = (positions[n], tree.nodes[n].parent.and_then(|p| positions[p])) {
+            tree.nodes[n].length = Some(x - p);
+        }
+    }
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FILE: &str = r#"{"version":"2.0","total_nodes":4,"mutations":[{"mutation_id":0,"gene":"nt","previous_residue":"C","residue_pos":241,"new_residue":"T"},{"mutation_id":1,"gene":"S","previous_residue":"D","residue_pos":614,"new_residue":"G"}]}
+{"node_id":0,"parent_id":0,"x_dist":0,"y":1,"num_tips":2,"mutations":[]}
+{"node_id":2,"parent_id":0,"x_dist":1.5,"name":"b","meta_country":"UK","meta_lineage":"","mutations":[0,1]}
+{"node_id":1,"parent_id":0,"x_dist":2,"name":"a","meta_country":"US","mutations":[]}
+"#;
+
+    #[test]
+    fn reads_nodes_metadata_and_mutations() {
+        assert!(is_taxonium(FILE));
+        let tree = parse_taxonium(FILE).unwrap();
+        let rows: Vec<_> = tree.nodes.iter().map(|n| (n.label.as_deref(), n.length)).collect();
+        assert_eq!(rows, [(None, None), (Some("b"), Some(1.5)), (Some("a"), Some(2.0))]);
+        let annots = &tree.nodes[1].annotations;
+        assert_eq!(annots["country"], json!("UK"));
+        assert!(!annots.contains_key("lineage"));
+        assert_eq!(annots["mutations"], json!(["C241T"]));
+        assert_eq!(annots["aa_mutations"], json!(["S:D614G"]));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let node = |id: i64, parent: i64| format!(r#"{{"node_id":{id},"parent_id":{parent}}}"#);
+        for text in [
+            format!("{}\n{{\"node_id\":1,", node(0, 0)),
+            format!("{}\n{}", node(0, 0), r#"{"node_id":1}"#),
+            format!("{}\n{}", node(0, 0), node(0, 0)),
+            format!("{}\n{}", node(0, 0), node(1, 7)),
+            format!("{}\n{}", node(0, 1), node(1, 0)),
+            format!("{}\n{}", node(0, 0), node(1, 1)),
+        ] {
+            assert!(parse_taxonium(&text).is_err(), "{text:?} parsed");
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Compact node-table representation of a phylogenetic tree.
+//!
+//! Every native reader produces a `Tree` and every native writer consumes one.
+//! Rows are stored in pre-order: the root is row 0 and a parent always comes
+//! before its children, so the table can be rebuilt into nested nodes (and fed
+//! to `fromNestedRoot` on the JS side) in a single forward pass.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// One row of the node table.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TreeNode {
+    /// Row index of the parent node, or `null` for the root.
+    pub parent: Option<usize>,
+    /// Branch length to the parent. Omitted when the file gave none, so the
+    /// frontend's missing-branch-length check sees `undefined` as it does for
+    /// trees parsed in JS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<f64>,
+    /// Taxon name for tips, or the raw node label for internal nodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub annotations: Map<String, Value>,
+}
+
+/// A parsed tree: its node table plus the metadata the file carried about it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tree {
+    /// Tree name (e.g. `TREE1` in `tree TREE1 = ...`), if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `Some(true)` for `[&R]`, `Some(false)` for `[&U]`, `None` when unstated.
+    #[serde(default)]
+    pub rooted: Option<bool>,
+    pub nodes: Vec<TreeNode>,
+}
+
+impl Tree {
+    /// Appends a node under `parent` and returns its row index.
+    pub fn push_node(&mut self, parent: Option<usize>) -> usize {
+        self.nodes.push(TreeNode { parent, ..Default::default() });
+        self.nodes.len() - 1
+    }
+
+    /// Row indices of all tips (nodes without children), in file order.
+    pub fn tips(&self) -> Vec<usize> {
+        let mut has_child = vec![false; self.nodes.len()];
+        for node in &self.nodes {
+            if let Some(p) = node.parent {
+                has_child[p] = true;
+            }
+        }
+        (0..self.nodes.len()).filter(|&i| !has_child[i]).collect()
+    }
+}
+
+/// Everything a tree file yields: its trees plus file-level extras. This is
+/// what `parse_tree` and `get_tree` return to the frontend.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeFile {
+    pub trees: Vec<Tree>,
+    /// PearTree settings embedded as `[peartree={...}]`, if present.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+    pub warnings: Vec<LoadWarning>,
+    /// Positions of all trees in a multi-tree file; fetch others with `get_tree`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<crate::treeset::TreeIndex>,
+    /// Text encoding the file was decoded from, e.g. `"UTF-8"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<&'static str>,
+}
+
+/// Non-fatal problems found while reading a file, for the frontend to show.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LoadWarning {
+    /// Tips of tree `tree` that had no TAXLABELS entry in the NEXUS TAXA
+    /// block, and so received no taxon annotations.
+    #[serde(rename_all = "camelCase")]
+    TaxaBlockMismatch { tree: usize, count: usize, names: Vec<String> },
+}
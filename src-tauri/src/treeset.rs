@@ -0,0 +1,138 @@
+//! An opened tree file kept in memory with an index of the trees it holds.
+//!
+//! BEAST posterior samples and bootstrap files hold many trees. Opening one
+//! builds a `TreeSet` (a single indexing pass, no tree is built), after which
+//! any tree can be parsed on demand by number without re-reading the file.
+//! XML formats are parsed up front instead, since a DOM parse reads the whole
+//! document anyway.
+
+use crate::newick::{self, ParseError};
+use crate::nexus::{self, NexusIndex, TreeEntry};
+use crate::support::{self, LabelOrigin};
+use crate::{auspice, jplace, nexml, phyloxml, taxonium};
+use crate::tree::{Tree, TreeFile};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The index returned to the frontend alongside the first tree.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeIndex {
+    pub count: usize,
+    pub entries: Vec<TreeEntry>,
+}
+
+pub struct TreeSet {
+    text: String,
+    source: Source,
+    /// Where the file came from, for naming support values in node labels.
+    origin: LabelOrigin,
+}
+
+enum Source {
+    Nexus(NexusIndex),
+    /// Start offset of each `;`-terminated tree in a Newick file.
+    Newick(Vec<usize>),
+    /// Trees already built by a whole-document reader (phyloXML, NeXML,
+    /// Auspice JSON, Taxonium JSONL, jplace), with any settings the document
+    /// implied.
+    Parsed(Vec<Tree>, Option<Value>),
+}
+
+impl TreeSet {
+    /// Indexes `text` as NEXUS (if it has the `#NEXUS` header) or as one or
+    /// more Newick trees, or parses it as phyloXML, NeXML, Taxonium JSONL,
+    /// jplace or Auspice JSON.
+    pub fn open(text: String) -> Result<Self, ParseError> {
+        let parsed = if phyloxml::is_phyloxml(&text) {
+            Some((phyloxml::parse_phyloxml(&text)?, None, "no <phylogeny> with clades found"))
+        } else if nexml::is_nexml(&text) {
+            Some((nexml::parse_nexml(&text)?, None, "no <tree> found"))
+        } else if taxonium::is_taxonium(&text) {
+            Some((vec![taxonium::parse_taxonium(&text)?], None, "no tree found"))
+        } else if jplace::is_jplace(&text) {
+            Some((vec![jplace::parse_jplace(&text)?], None, "no tree found"))
+        } else if auspice::is_auspice(&text) {
+            let (trees, settings) = auspice::parse_auspice(&text)?;
+            Some((trees, settings, "no tree found"))
+        } else {
+            None
+        };
+        if let Some((trees, settings, empty_message)) = parsed {
+            if trees.is_empty() {
+                return Err(ParseError::new(0, empty_message));
+            }
+            return Ok(TreeSet { text: String::new(), source: Source::Parsed(trees, settings), origin: LabelOrigin::Unknown });
+        }
+        let source = if nexus::is_nexus(&text) {
+            Source::Nexus(nexus::index_nexus(&text)?)
+        } else {
+            Source::Newick(newick::tree_offsets(&text)?)
+        };
+        Ok(TreeSet { text, source, origin: LabelOrigin::Unknown })
+    }
+
+    /// Names the support values found in NEXUS and Newick node labels as
+    /// the program that wrote the file (see `support.rs`) would.
+    pub fn with_label_origin(mut self, origin: LabelOrigin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.source {
+            Source::Nexus(index) => index.trees.len(),
+            Source::Newick(offsets) => offsets.len(),
+            Source::Parsed(trees, _) => trees.len(),
+        }
+    }
+
+    pub fn index(&self) -> TreeIndex {
+        let entries = match &self.source {
+            Source::Nexus(index) => index.trees.clone(),
+            Source::Newick(offsets) => offsets
+                .iter()
+                .map(|&offset| TreeEntry { name: None, offset, ..Default::default() })
+                .collect(),
+            Source::Parsed(trees, _) => trees
+                .iter()
+                .map(|t| TreeEntry { name: t.name.clone(), ..Default::default() })
+                .collect(),
+        };
+        TreeIndex { count: entries.len(), entries }
+    }
+
+    /// Parses tree `i` alone, without the file-level extras.
+    pub fn tree(&self, i: usize) -> Result<Tree, ParseError> {
+        Ok(self.tree_file(i)?.trees.remove(0))
+    }
+
+    /// Parses tree `i` and wraps it, with the file's settings and any warnings
+    /// for that tree, in the `TreeFile` shape `parse_tree` returns.
+    pub fn tree_file(&self, i: usize) -> Result<TreeFile, ParseError> {
+        if i >= self.len() {
+            return Err(ParseError::new(0, format!("tree {} requested but the file has {}", i + 1, self.len())));
+        }
+        match &self.source {
+            Source::Nexus(index) => {
+                let (mut tree, warning) = index.read_tree(&self.text, i)?;
+                support::split_support_labels(&mut tree, self.origin);
+                Ok(TreeFile {
+                    trees: vec![tree],
+                    settings: index.settings.clone(),
+                    warnings: warning.into_iter().collect(),
+                    ..Default::default()
+                })
+            }
+            Source::Newick(offsets) => {
+                let mut tree = newick::Parser::new(&self.text, offsets[i]).parse_tree()?;
+                support::split_support_labels(&mut tree, self.origin);
+                Ok(TreeFile { trees: vec![tree], ..Default::default() })
+            }
+            Source::Parsed(trees, settings) => Ok(TreeFile {
+                trees: vec![trees[i].clone()],
+                settings: settings.clone(),
+                ..Default::default()
+            }),
+        }
+    }
+}
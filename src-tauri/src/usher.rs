@@ -0,0 +1,446 @@
+//! UShER mutation-annotated tree (MAT) reader.
+//!
+//! A MAT `.pb` file is a protobuf `data` message (see UShER's
+//! `parsimony.proto`): the tree as a Newick string, one mutation list per
+//! node in pre-order, condensed leaves (tips standing for several identical
+//! samples) and optional clade annotations. The few messages involved are
+//! decoded directly from the wire format.
+//!
+//! Each node's mutations become a `mutations` list (`C241T`, parent base
+//! first) on the branch above it, and clade annotations become `clade_1`,
+//! `clade_2`, ... on the node where the clade starts. Condensed leaves are
+//! expanded into sibling tips sharing the leaf's branch.
+//!
+//! `neighbourhood` cuts a manageable subtree around chosen samples out of a
+//! tree too big to open whole.
+
+use crate::newick::{self, ParseError};
+use crate::tree::{Tree, TreeNode};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Protobuf field numbers of the `data` message.
+const DATA_NEWICK: u64 = 1;
+const DATA_NODE_MUTATIONS: u64 = 2;
+const DATA_CONDENSED_NODES: u64 = 3;
+const DATA_METADATA: u64 = 4;
+
+const NUCLEOTIDES: [char; 4] = ['A', 'C', 'G', 'T'];
+
+/// Returns true when `bytes` starts like a MAT: field 1, length-delimited
+/// and within the buffer, holding a whole Newick string in UTF-8 that opens
+/// with `(` and ends with `;`. A text tree that happens to open with a
+/// newline and `(` fails the length check or the terminator.
+pub fn is_mat(bytes: &[u8]) -> bool {
+    let mut wire = Wire { bytes, pos: 0 };
+    let Ok(Some((DATA_NEWICK, Field::Bytes(newick)))) = wire.field() else { return false };
+    let Ok(newick) = std::str::from_utf8(newick) else { return false };
+    let newick = newick.trim();
+    newick.starts_with('(') && newick.ends_with(';')
+}
+
+/// Reads a MAT into a node table.
+pub fn parse_mat(bytes: &[u8]) -> Result<Tree, ParseError> {
+    let mut wire = Wire { bytes, pos: 0 };
+    let mut newick_text = None;
+    let mut mutations = Vec::new();
+    let mut condensed = HashMap::new();
+    let mut clades = Vec::new();
+    loop {
+        let at = wire.pos;
+        let Some((number, field)) = wire.field()? else { break };
+        match (number, field) {
+            (DATA_NEWICK, Field::Bytes(b)) => {
+                newick_text = Some(std::str::from_utf8(b).map_err(|e| ParseError::new(at, e.to_string()))?);
+            }
+            (DATA_NODE_MUTATIONS, Field::Bytes(b)) => mutations.push(read_mutation_list(b)?),
+            (DATA_CONDENSED_NODES, Field::Bytes(b)) => {
+                let (name, leaves) = read_condensed_node(b)?;
+                condensed.insert(name, leaves);
+            }
+            (DATA_METADATA, Field::Bytes(b)) => clades.push(read_strings(b, 1)?),
+            _ => {}
+        }
+    }
+    let text = newick_text.ok_or_else(|| ParseError::new(0, "MAT has no Newick tree"))?;
+    let mut tree = newick::Parser::new(text, 0).parse_tree()?;
+    tree.rooted = Some(true);
+    if !mutations.is_empty() && mutations.len() != tree.nodes.len() {
+        return Err(ParseError::new(
+            0,
+            format!("MAT has mutations for {} nodes but its tree has {}", mutations.len(), tree.nodes.len()),
+        ));
+    }
+    for (node, muts) in tree.nodes.iter_mut().zip(mutations) {
+        if !muts.is_empty() {
+            node.annotations.insert("mutations".into(), muts.into_iter().map(Value::from).collect());
+        }
+    }
+    for (node, names) in tree.nodes.iter_mut().zip(clades) {
+        for (i, name) in names.into_iter().enumerate().filter(|(_, n)| !n.is_empty()) {
+            node.annotations.insert(format!("clade_{}", i + 1), Value::from(name));
+        }
+    }
+    Ok(expand_condensed(tree, &condensed))
+}
+
+/// `mutation_list { repeated mut mutation = 1; }` as `C241T`-style strings.
+fn read_mutation_list(bytes: &[u8]) -> Result<Vec<String>, ParseError> {
+    let mut wire = Wire { bytes, pos: 0 };
+    let mut out = Vec::new();
+    while let Some((number, field)) = wire.field()? {
+        if let (1, Field::Bytes(b)) = (number, field) {
+            out.push(read_mutation(b)?);
+        }
+    }
+    Ok(out)
+}
+
+/// `mut { int32 position = 1; int32 ref_nuc = 2; int32 par_nuc = 3;
+/// repeated int32 mut_nuc = 4; }`, with bases coded 0–3 for A, C, G, T.
+fn read_mutation(bytes: &[u8]) -> Result<String, ParseError> {
+    let mut wire = Wire { bytes, pos: 0 };
+    let (mut position, mut par, mut alt) = (0, 0, Vec::new());
+    while let Some((number, field)) = wire.field()? {
+        match (number, field) {
+            (1, Field::Varint(v)) => position = v,
+            (3, Field::Varint(v)) => par = v,
+            (4, Field::Varint(v)) => alt.push(v),
+            (4, Field::Bytes(packed)) => {
+                let mut inner = Wire { bytes: packed, pos: 0 };
+                while inner.pos < packed.len() {
+                    alt.push(inner.varint()?);
+                }
+            }
+            _ => {}
+        }
+    }
+    let base = |n: u64| NUCLEOTIDES.get(n as usize).copied().unwrap_or('N');
+    let alt: String = alt.into_iter().map(base).collect();
+    Ok(format!("{}{position}{alt}", base(par)))
+}
+
+/// `condensed_node { string node_name = 1; repeated string condensed_leaves = 2; }`
+fn read_condensed_node(bytes: &[u8]) -> Result<(String, Vec<String>), ParseError> {
+    let name = read_strings(bytes, 1)?.into_iter().next().unwrap_or_default();
+    Ok((name, read_strings(bytes, 2)?))
+}
+
+/// Every string in field `number` of a message.
+fn read_strings(bytes: &[u8], number: u64) -> Result<Vec<String>, ParseError> {
+    let mut wire = Wire { bytes, pos: 0 };
+    let mut out = Vec::new();
+    while let Some((n, field)) = wire.field()? {
+        if let (true, Field::Bytes(b)) = (n == number, field) {
+            out.push(String::from_utf8_lossy(b).into_owned());
+        }
+    }
+    Ok(out)
+}
+
+/// Replaces each condensed leaf with its samples: the first takes the
+/// leaf's row, the rest follow it as siblings with the same branch. Inserting
+/// them straight after the leaf keeps the table in pre-order.
+fn expand_condensed(tree: Tree, condensed: &HashMap<String, Vec<String>>) -> Tree {
+    if condensed.is_empty() {
+        return tree;
+    }
+    let mut out = Tree { name: tree.name, rooted: tree.rooted, nodes: Vec::with_capacity(tree.nodes.len()) };
+    let mut new_row = Vec::with_capacity(tree.nodes.len());
+    for mut node in tree.nodes {
+        node.parent = node.parent.map(|p| new_row[p]);
+        new_row.push(out.nodes.len());
+        let samples = node.label.as_ref().and_then(|l| condensed.get(l)).filter(|s| !s.is_empty());
+        let Some(samples) = samples else {
+            out.nodes.push(node);
+            continue;
+        };
+        for sample in samples {
+            out.nodes.push(TreeNode { label: Some(sample.clone()), ..node.clone() });
+        }
+    }
+    out
+}
+
+/// The subtree spanned by the neighbourhoods of `samples`: for each sample,
+/// the smallest clade around it with at least `size` tips. Nodes left with a
+/// single child are merged into it, summing branch lengths and concatenating
+/// mutations.
+pub fn neighbourhood(tree: &Tree, samples: &[String], size: usize) -> Result<Tree, String> {
+    if samples.is_empty() {
+        return Err("No samples given".into());
+    }
+    let count = tree.nodes.len();
+    let mut is_tip = vec![true; count];
+    for node in &tree.nodes {
+        if let Some(p) = node.parent {
+            is_tip[p] = false;
+        }
+    }
+    // Tip counts: every tip counts one, every internal node the sum of its children.
+    let mut tips_below = vec![0usize; count];
+    for n in (0..count).rev() {
+        if is_tip[n] {
+            tips_below[n] += 1;
+        }
+        if let Some(p) = tree.nodes[n].parent {
+            tips_below[p] += tips_below[n];
+        }
+    }
+
+    let row_of: HashMap<&str, usize> = (0..count)
+        .filter(|&n| is_tip[n])
+        .filter_map(|n| Some((tree.nodes[n].label.as_deref()?, n)))
+        .collect();
+    let missing: Vec<&str> = samples.iter().map(String::as_str).filter(|s| !row_of.contains_key(s)).collect();
+    if !missing.is_empty() {
+        let shown = missing.iter().take(5).copied().collect::<Vec<_>>().join(", ");
+        let more = if missing.len() > 5 { format!(" and {} more", missing.len() - 5) } else { String::new() };
+        return Err(format!("Samples not found in the tree: {shown}{more}"));
+    }
+
+    let mut clade_root = vec![false; count];
+    for sample in samples {
+        let mut n = row_of[sample.as_str()];
+        while tips_below[n] < size {
+            match tree.nodes[n].parent {
+                Some(p) => n = p,
+                None => break,
+            }
+        }
+        clade_root[n] = true;
+    }
+    // Tips inside a chosen clade are kept; a node is needed if it leads to one.
+    let mut inside = vec![false; count];
+    for n in 0..count {
+        inside[n] = clade_root[n] || tree.nodes[n].parent.is_some_and(|p| inside[p]);
+    }
+    let mut needed = vec![false; count];
+    let mut needed_children = vec![0usize; count];
+    for n in (0..count).rev() {
+        needed[n] |= is_tip[n] && inside[n];
+        if let (true, Some(p)) = (needed[n], tree.nodes[n].parent) {
+            needed[p] = true;
+            needed_children[p] += 1;
+        }
+    }
+
+    // A merged node is held back as the start of its remaining child's row:
+    // new parent, branch length so far and annotations.
+    let mut out = Tree { name: tree.name.clone(), rooted: Some(true), nodes: Vec::new() };
+    let mut new_row: Vec<Option<usize>> = vec![None; count];
+    let mut merged: HashMap<usize, TreeNode> = HashMap::new();
+    for n in (0..count).filter(|&n| needed[n]) {
+        let node = &tree.nodes[n];
+        let mut row = match node.parent {
+            Some(p) if new_row[p].is_some() => TreeNode { parent: new_row[p], ..Default::default() },
+            Some(p) => merged.remove(&p).unwrap_or_default(),
+            None => TreeNode::default(),
+        };
+        if let (Some(_), Some(length)) = (row.parent, node.length) {
+            row.length = Some(row.length.unwrap_or(0.0) + length);
+        }
+        merge_annotations(&mut row.annotations, &node.annotations);
+        if !is_tip[n] && needed_children[n] == 1 {
+            merged.insert(n, row);
+            continue;
+        }
+        row.label = node.label.clone();
+        new_row[n] = Some(out.nodes.len());
+        out.nodes.push(row);
+    }
+    Ok(out)
+}
+
+/// Adds `from` to `into`, appending to `mutations` rather than replacing it.
+fn merge_annotations(into: &mut Map<String, Value>, from: &Map<String, Value>) {
+    for (key, value) in from {
+        match (into.get_mut(key), value) {
+            (Some(Value::Array(list)), Value::Array(more)) if key == "mutations" => list.extend(more.iter().cloned()),
+            _ => {
+                into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// A decoded protobuf field value. Fixed-width fields are skipped.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Cursor over protobuf wire-format bytes.
+struct Wire<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Wire<'a> {
+    fn varint(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| ParseError::new(self.pos, "truncated protobuf varint"))?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ParseError::new(self.pos, "protobuf varint too long"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| ParseError::new(self.pos, "truncated protobuf field"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    /// The next field number and value, or `None` at the end of the message.
+    fn field(&mut self) -> Result<Option<(u64, Field<'a>)>, ParseError> {
+        if self.pos >= self.bytes.len() {
+            return Ok(None);
+        }
+        let at = self.pos;
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Field::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Field::Fixed
+            }
+            wire_type => return Err(ParseError::new(at, format!("unsupported protobuf wire type {wire_type}"))),
+        };
+        Ok(Some((key >> 3, field)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn bytes_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+        varint(out, number << 3 | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    fn varint_field(out: &mut Vec<u8>, number: u64, value: u64) {
+        varint(out, number << 3);
+        varint(out, value);
+    }
+
+    /// A `mut` message: position, parent base and alternative bases (0–3).
+    fn mutation(position: u64, par: u64, alt: &[u64]) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint_field(&mut out, 1, position);
+        varint_field(&mut out, 3, par);
+        for &a in alt {
+            varint_field(&mut out, 4, a);
+        }
+        out
+    }
+
+    /// A MAT of `newick` with one mutation list per entry of `mutations`.
+    fn mat(newick: &str, mutations: &[&[Vec<u8>]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes_field(&mut out, DATA_NEWICK, newick.as_bytes());
+        for list in mutations {
+            let mut message = Vec::new();
+            for m in *list {
+                bytes_field(&mut message, 1, m);
+            }
+            bytes_field(&mut out, DATA_NODE_MUTATIONS, &message);
+        }
+        out
+    }
+
+    const NEWICK: &str = "((a:1,b:1):1,c:2);";
+
+    #[test]
+    fn reads_mutations_clades_and_condensed_leaves() {
+        let mut bytes = mat(NEWICK, &[&[], &[mutation(241, 1, &[3])], &[mutation(1, 0, &[2]), mutation(5, 3, &[0])], &[], &[]]);
+        let mut condensed = Vec::new();
+        bytes_field(&mut condensed, 1, b"c");
+        bytes_field(&mut condensed, 2, b"c1");
+        bytes_field(&mut condensed, 2, b"c2");
+        bytes_field(&mut bytes, DATA_CONDENSED_NODES, &condensed);
+        for clades in [&[][..], &["20I", ""], &[], &[], &[]] {
+            let mut message = Vec::new();
+            for clade in clades {
+                bytes_field(&mut message, 1, clade.as_bytes());
+            }
+            bytes_field(&mut bytes, DATA_METADATA, &message);
+        }
+        assert!(is_mat(&bytes));
+        let tree = parse_mat(&bytes).unwrap();
+        let labels: Vec<_> = tree.nodes.iter().map(|n| n.label.as_deref()).collect();
+        assert_eq!(labels, [None, None, Some("a"), Some("b"), Some("c1"), Some("c2")]);
+        assert_eq!(tree.nodes[1].annotations["mutations"], json!(["C241T"]));
+        assert_eq!(tree.nodes[2].annotations["mutations"], json!(["A1G", "T5A"]));
+        assert_eq!(tree.nodes[1].annotations["clade_1"], json!("20I"));
+        assert!(!tree.nodes[1].annotations.contains_key("clade_2"));
+        assert_eq!((tree.nodes[4].parent, tree.nodes[4].length), (Some(0), Some(2.0)));
+        assert_eq!((tree.nodes[5].parent, tree.nodes[5].length), (Some(0), Some(2.0)));
+    }
+
+    #[test]
+    fn only_takes_whole_newick_fields_as_mats() {
+        assert!(!is_mat(b"\n((a,b),c);\n"));
+        assert!(!is_mat(format!("\n({});\n", ["t"; 40].join(",")).as_bytes()));
+        assert!(!is_mat(&mat("(a,b)", &[])));
+        assert!(!is_mat(&mat("(a,b);", &[])[..6]));
+        let mut bad_utf8 = mat("(a,b);", &[]);
+        bad_utf8[3] = 0xff;
+        assert!(!is_mat(&bad_utf8));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let full = mat(NEWICK, &[&[], &[mutation(241, 1, &[3])], &[], &[], &[]]);
+        let mut no_tree = Vec::new();
+        varint_field(&mut no_tree, 9, 1);
+        for bytes in [
+            full[..full.len() - 3].to_vec(),
+            mat(NEWICK, &[&[], &[]]),
+            mat("((a,b);", &[]),
+            no_tree,
+            vec![0x0b],
+        ] {
+            assert!(parse_mat(&bytes).is_err(), "{bytes:?} parsed");
+        }
+    }
+
+    #[test]
+    fn cuts_neighbourhoods_around_samples() {
+        let tree = newick::Parser::new("(((a:1,b:1):1,c:1)[&mutations={C1T}]:1,(d:1,e:1):1);", 0).parse_tree().unwrap();
+        let sub = neighbourhood(&tree, &["a".into()], 3).unwrap();
+        let labels: Vec<_> = sub.tips().iter().map(|&t| sub.nodes[t].label.clone().unwrap()).collect();
+        assert_eq!(labels, ["a", "b", "c"]);
+        let sub = neighbourhood(&tree, &["a".into(), "e".into()], 1).unwrap();
+        let labels: Vec<_> = sub.nodes.iter().map(|n| (n.label.as_deref(), n.length)).collect();
+        assert_eq!(labels, [(None, None), (Some("a"), Some(3.0)), (Some("e"), Some(2.0))]);
+        assert_eq!(sub.nodes[1].annotations["mutations"], json!(["C1T"]));
+        assert!(neighbourhood(&tree, &["x".into()], 3).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+//! Native Newick / NEXUS / NHX writer, and the entry point for the XML and
+//! JSON formats (see `phyloxml`, `nexml` and `auspice`).
+//!
+//! Serialises a node table straight to a file, so exporting a large tree
+//! never builds the whole output string in JS or sends it over IPC. Labels
+//! are single-quoted when they contain anything beyond letters, digits and
+//! `_ . - |`; annotations are written BEAST-style as `[&key=value,...]`
+//! after the node label, with strings double-quoted (`\"` and `\\` escaped,
+//! as `newick::parse_annotations` reads them) and arrays as `{...}`. Keys
+//! holding delimiters are quoted the same way.
+//! NHX writes the same annotations as `[&&NHX:key=value:...]` after the
+//! branch length instead.
+//! Like the parser, the writer is iterative and safe on very deep trees.
+
+use crate::tree::{Tree, TreeNode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Format {
+    Newick,
+    #[default]
+    Nexus,
+    Nhx,
+    #[serde(rename = "phyloxml")]
+    PhyloXml,
+    #[serde(rename = "nexml")]
+    NeXml,
+    Auspice,
+}
+
+/// What to write, as chosen in the Export Tree dialog.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WriteOptions {
+    pub format: Format,
+    /// Annotation to write as the tip name instead of the tip's label.
+    pub tip_label: Option<String>,
+    /// Annotation to write as the internal-node label.
+    pub node_label: Option<String>,
+    /// Annotations to write, in this order.
+    pub annotations: Vec<String>,
+    /// PearTree settings to embed as `[peartree={...}]` (NEXUS), or to take
+    /// fixed palettes and the tip colouring from (Auspice).
+    pub settings: Option<Value>,
+    /// Row of the node to export from, for a subtree; `None` for the whole tree.
+    pub subtree: Option<usize>,
+    /// Annotations read from Auspice branch labels (`k_label`), written back
+    /// to `branch_attrs.labels` by the Auspice writer.
+    pub branch_labels: Vec<String>,
+}
+
+enum Step {
+    Open(usize),
+    Separator,
+    Close(usize),
+}
+
+/// Writes `tree` in the format given by `opts`.
+pub fn write_tree(out: &mut impl Write, tree: &Tree, opts: &WriteOptions) -> io::Result<()> {
+    let root = opts.subtree.unwrap_or(0);
+    if root >= tree.nodes.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no node {root} to export")));
+    }
+    match opts.format {
+        Format::Newick | Format::Nhx => {
+            write_newick(out, tree, root, opts)?;
+            writeln!(out)
+        }
+        Format::PhyloXml => crate::phyloxml::write_phyloxml(out, tree, root, opts),
+        Format::NeXml => crate::nexml::write_nexml(out, tree, root, opts),
+        Format::Auspice => crate::auspice::write_auspice(out, tree, root, opts),
+        Format::Nexus => {
+            out.write_all(b"#NEXUS\nBEGIN TREES;\n\ttree TREE1 = ")?;
+            // The rooting tag goes with BEAST-style annotations, as before.
+            if !opts.annotations.is_empty() {
+                out.write_all(b"[&R] ")?;
+            }
+            write_newick(out, tree, root, opts)?;
+            writeln!(out)?;
+            if let Some(settings) = &opts.settings {
+                writeln!(out, "\t[peartree={settings}]")?;
+            }
+            writeln!(out, "END;")
+        }
+    }
+}
+
+/// Writes the Newick string (with trailing `;`) for the subtree at `root`.
+fn write_newick(out: &mut impl Write, tree: &Tree, root: usize, opts: &WriteOptions) -> io::Result<()> {
+    let children = children_of(tree);
+    let mut stack = vec![Step::Open(root)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Open(n) if !children[n].is_empty() => {
+                out.write_all(b"(")?;
+                stack.push(Step::Close(n));
+                for (k, &c) in children[n].iter().enumerate().rev() {
+                    stack.push(Step::Open(c));
+                    if k > 0 {
+                        stack.push(Step::Separator);
+                    }
+                }
+            }
+            Step::Open(n) => write_node(out, tree, n, n == root, true, opts)?,
+            Step::Separator => out.write_all(b",")?,
+            Step::Close(n) => {
+                out.write_all(b")")?;
+                write_node(out, tree, n, n == root, false, opts)?;
+            }
+        }
+    }
+    out.write_all(b";")
+}
+
+/// Child rows of every node, in table order.
+pub(crate) fn children_of(tree: &Tree) -> Vec<Vec<usize>> {
+    let mut children = vec![Vec::new(); tree.nodes.len()];
+    for (i, node) in tree.nodes.iter().enumerate() {
+        if let Some(p) = node.parent {
+            children[p].push(i);
+        }
+    }
+    children
+}
+
+/// The label to write for node `n`: the annotation chosen as the tip or node
+/// label, else the node's own label. Tips fall back to their own label when
+/// the chosen annotation is missing.
+pub(crate) fn display_label(tree: &Tree, n: usize, is_tip: bool, opts: &WriteOptions) -> Option<String> {
+    let node = &tree.nodes[n];
+    let field = if is_tip { &opts.tip_label } else { &opts.node_label };
+    let chosen = field.as_ref().and_then(|key| match node.annotations.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        v => Some(v.to_string()),
+    });
+    chosen.or_else(|| if is_tip || field.is_none() { node.label.clone() } else { None })
+}
+
+/// Writes a node's label, annotation comment and branch length.
+fn write_node(out: &mut impl Write, tree: &Tree, n: usize, is_root: bool, is_tip: bool, opts: &WriteOptions) -> io::Result<()> {
+    let node = &tree.nodes[n];
+    if let Some(label) = display_label(tree, n, is_tip, opts) {
+        write_label(out, &label)?;
+    }
+    let nhx = opts.format == Format::Nhx;
+    if !nhx {
+        write_annotations(out, node, opts)?;
+    }
+    if let (Some(length), false) = (node.length, is_root) {
+        write!(out, ":{length}")?;
+    }
+    if nhx {
+        write_nhx(out, node, opts)?;
+    }
+    Ok(())
+}
+
+/// Writes the chosen annotations as a BEAST-style `[&key=value,...]` comment.
+fn write_annotations(out: &mut impl Write, node: &TreeNode, opts: &WriteOptions) -> io::Result<()> {
+    let mut first = true;
+    for key in &opts.annotations {
+        let Some(value) = node.annotations.get(key).filter(|v| !v.is_null()) else { continue };
+        out.write_all(if first { b"[&" } else { b"," })?;
+        first = false;
+        let plain = !key.is_empty() && key.chars().all(|c| !c.is_whitespace() && !matches!(c, '=' | ',' | '[' | ']' | '{' | '}' | '"' | '\''));
+        if plain {
+            out.write_all(key.as_bytes())?;
+        } else {
+            write_quoted(out, key)?;
+        }
+        out.write_all(b"=")?;
+        write_value(out, value)?;
+    }
+    if !first {
+        out.write_all(b"]")?;
+    }
+    Ok(())
+}
+
+/// Writes the chosen annotations as an NHX `[&&NHX:key=value:...]` comment.
+/// NHX keys and values are unquoted, so characters that would end a field,
+/// the comment or the tree become `_` (see `nhx_text`). Arrays keep their
+/// `{a,b}` form, which the parser reads back.
+fn write_nhx(out: &mut impl Write, node: &TreeNode, opts: &WriteOptions) -> io::Result<()> {
+    let mut first = true;
+    for key in &opts.annotations {
+        let text = match node.annotations.get(key) {
+            None | Some(Value::Null) => continue,
+            Some(Value::String(s)) => nhx_text(s),
+            Some(Value::Array(items)) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .filter(|v| !v.is_null())
+                    .map(|v| nhx_text(&v.as_str().map_or_else(|| v.to_string(), str::to_string)))
+                    .collect();
+                format!("{{{}}}", items.join(","))
+            }
+            Some(v) => nhx_text(&v.to_string()),
+        };
+        if first {
+            out.write_all(b"[&&NHX")?;
+            first = false;
+        }
+        write!(out, ":{}={text}", nhx_text(key))?;
+    }
+    if !first {
+        out.write_all(b"]")?;
+    }
+    Ok(())
+}
+
+/// An NHX key or value with `:`, `=`, brackets, double quotes, commas and
+/// parentheses replaced by `_`.
+fn nhx_text(text: &str) -> String {
+    text.chars().map(|c| if matches!(c, ':' | '=' | '[' | ']' | '"' | ',' | '(' | ')') { '_' } else { c }).collect()
+}
+
+/// Writes a taxon name or label, quoting it if it holds punctuation or spaces.
+fn write_label(out: &mut impl Write, label: &str) -> io::Result<()> {
+    let plain = !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '|'));
+    if plain {
+        out.write_all(label.as_bytes())
+    } else {
+        write!(out, "'{}'", label.replace('\'', "''"))
+    }
+}
+
+/// Writes an annotation value: numbers and booleans bare, strings quoted,
+/// arrays as `{a,b,...}`. Nulls write nothing and are left out of arrays,
+/// as they are left out of the comments, here and in NHX.
+pub(crate) fn write_value(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::String(s) => write_quoted(out, s),
+        Value::Array(items) => {
+            out.write_all(b"{")?;
+            for (i, item) in items.iter().filter(|v| !v.is_null()).enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write_value(out, item)?;
+            }
+            out.write_all(b"}")
+        }
+        Value::Null => Ok(()),
+        other => write!(out, "{other}"),
+    }
+}
+
+/// Writes a double-quoted annotation string, escaping `"` and `\` with a
+/// backslash.
+fn write_quoted(out: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(out, "\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escapes text for use in XML content or a double-quoted attribute.
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::treeset::TreeSet;
+    use serde_json::json;
+
+    /// `((a,'b c'),d)` with awkward annotations on every tip.
+    fn sample() -> Tree {
+        let mut tree = Tree::default();
+        let root = tree.push_node(None);
+        let inner = tree.push_node(Some(root));
+        tree.nodes[inner].length = Some(0.5);
+        tree.nodes[inner].annotations.insert("posterior".into(), json!(0.95));
+        for (parent, label) in [(inner, "a"), (inner, "b c"), (root, "d")] {
+            let n = tree.push_node(Some(parent));
+            let node = &mut tree.nodes[n];
+            node.label = Some(label.into());
+            node.length = Some(1.25);
+            node.annotations.insert("host".into(), json!(r#"it's "odd", [x] \ y"#));
+            node.annotations.insert("odd=key, ]".into(), json!(7));
+            node.annotations.insert("set".into(), json!(["p'q", 2, null, "r,s"]));
+        }
+        tree
+    }
+
+    fn options(format: Format, annotations: &[&str]) -> WriteOptions {
+        WriteOptions { format, annotations: annotations.iter().map(|k| k.to_string()).collect(), ..Default::default() }
+    }
+
+    fn write(tree: &Tree, opts: &WriteOptions) -> String {
+        let mut out = Vec::new();
+        write_tree(&mut out, tree, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn read(text: &str) -> Tree {
+        TreeSet::open(text.to_string()).and_then(|set| set.tree(0)).unwrap()
+    }
+
+    fn assert_same_shape(a: &Tree, b: &Tree) {
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (x, y) in a.nodes.iter().zip(&b.nodes) {
+            assert_eq!((x.parent, x.length), (y.parent, y.length));
+        }
+        let tips = |t: &Tree| t.tips().iter().map(|&n| t.nodes[n].label.clone()).collect::<Vec<_>>();
+        assert_eq!(tips(a), tips(b));
+    }
+
+    #[test]
+    fn nexus_round_trips_annotations() {
+        let tree = sample();
+        let text = write(&tree, &options(Format::Nexus, &["host", "odd=key, ]", "set", "posterior"]));
+        assert!(text.contains("[&R] "));
+        let back = read(&text);
+        assert_same_shape(&tree, &back);
+        for n in 1..tree.nodes.len() {
+            let mut expected = tree.nodes[n].annotations.clone();
+            if let Some(Value::Array(items)) = expected.get_mut("set") {
+                items.retain(|v| !v.is_null());
+            }
+            assert_eq!(back.nodes[n].annotations, expected);
+        }
+    }
+
+    #[test]
+    fn nexus_without_annotations_has_no_rooting_tag() {
+        let text = write(&sample(), &options(Format::Nexus, &[]));
+        assert!(!text.contains("[&R]"));
+        assert_same_shape(&sample(), &read(&text));
+    }
+
+    #[test]
+    fn newick_round_trips_labels_and_lengths() {
+        let tree = sample();
+        let text = write(&tree, &options(Format::Newick, &[]));
+        assert_eq!(text, "((a:1.25,'b c':1.25):0.5,d:1.25);\n");
+        assert_same_shape(&tree, &read(&text));
+    }
+
+    #[test]
+    fn nhx_round_trips_sanitised_annotations() {
+        let tree = sample();
+        let text = write(&tree, &options(Format::Nhx, &["host", "odd=key, ]", "set"]));
+        let back = read(&text);
+        assert_same_shape(&tree, &back);
+        let annots = &back.nodes[2].annotations;
+        assert_eq!(annots["host"], json!("it's _odd__ _x_ \\ y"));
+        assert_eq!(annots["odd_key_ _"], json!(7));
+        assert_eq!(annots["set"], json!(["p'q", 2, "r_s"]));
+    }
+
+    #[test]
+    fn writes_subtrees() {
+        let opts = WriteOptions { subtree: Some(1), ..options(Format::Newick, &[]) };
+        assert_eq!(write(&sample(), &opts), "(a:1.25,'b c':1.25);\n");
+        let opts = WriteOptions { subtree: Some(9), ..opts };
+        assert!(write_tree(&mut Vec::new(), &sample(), &opts).is_err());
+    }
+}